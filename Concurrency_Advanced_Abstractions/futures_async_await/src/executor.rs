@@ -0,0 +1,405 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+
+/// A spawned future. It outputs `()` because `spawn` wraps the user's future
+/// in one that hands the output to the `JoinHandle`.
+type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// The id under which `block_on`'s own future is woken up.
+const MAIN: usize = 0;
+
+thread_local! {
+    /// The executor of the `block_on` running on this thread, for `spawn`.
+    static CURRENT: RefCell<Option<Rc<Executor>>> = const { RefCell::new(None) };
+}
+
+/// The ids of the tasks that have been woken up and should be polled again.
+///
+/// Wakers may be called from any thread, for example by a timer thread, so
+/// this is the one part of the executor that is shared between threads.
+struct ReadyQueue {
+    ready: Mutex<VecDeque<usize>>,
+    /// The thread running `block_on`, parked while no task is ready.
+    thread: Thread,
+}
+
+impl ReadyQueue {
+    fn push(&self, id: usize) {
+        self.ready
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push_back(id);
+        // If `block_on` is not parked yet, its next `park` returns at once, so
+        // the wake-up cannot get lost.
+        self.thread.unpark();
+    }
+
+    fn pop(&self) -> Option<usize> {
+        // Pushing and popping cannot leave the queue half updated, so a panic
+        // in another thread while it held the lock does not matter.
+        self.ready
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop_front()
+    }
+}
+
+/// The waker of one task.
+struct TaskWaker {
+    id: usize,
+    queue: Arc<ReadyQueue>,
+    /// Set while the task is in the ready queue, so that waking it up several
+    /// times before it is polled only queues it once.
+    queued: AtomicBool,
+}
+
+impl TaskWaker {
+    fn new(id: usize, queue: &Arc<ReadyQueue>) -> Arc<TaskWaker> {
+        Arc::new(TaskWaker {
+            id,
+            queue: Arc::clone(queue),
+            queued: AtomicBool::new(false),
+        })
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.queue.push(self.id);
+        }
+    }
+}
+
+/// The state of a single `block_on` call.
+struct Executor {
+    /// Spawned tasks that are not being polled right now, with their wakers.
+    tasks: RefCell<HashMap<usize, (Task, Arc<TaskWaker>)>>,
+    next_id: Cell<usize>,
+    queue: Arc<ReadyQueue>,
+}
+
+impl Executor {
+    fn spawn(&self, task: Task) {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        let waker = TaskWaker::new(id, &self.queue);
+        self.tasks
+            .borrow_mut()
+            .insert(id, (task, Arc::clone(&waker)));
+        // A new task has to be polled once to get going.
+        waker.wake();
+    }
+
+    /// Polls the task with the given id, if it still exists.
+    fn poll_task(&self, id: usize) {
+        // The task is taken out of the map while it runs, so that it can
+        // `spawn` new tasks without finding the map borrowed.
+        let Some((mut task, waker)) = self.tasks.borrow_mut().remove(&id) else {
+            // It finished after it had been woken up once more.
+            return;
+        };
+        // Cleared before polling: a wake-up during the poll queues it again.
+        waker.queued.store(false, Ordering::Release);
+        let poll = task
+            .as_mut()
+            .poll(&mut Context::from_waker(&Waker::from(Arc::clone(&waker))));
+        if poll.is_pending() {
+            self.tasks.borrow_mut().insert(id, (task, waker));
+        }
+    }
+}
+
+/// Runs `future` to completion on the current thread, together with every
+/// task it `spawn`s, and returns its output.
+///
+/// Tasks are polled one at a time, in the order in which they were woken up.
+/// When none is ready, the thread parks until a waker unparks it, so waiting
+/// does not burn any CPU. Tasks that are still unfinished when `future`
+/// completes are dropped. A panic in any task unwinds out of `block_on`.
+///
+/// # Panics
+///
+/// Panics if it is called from within another `block_on` on the same thread:
+/// the outer executor could not run its tasks while the inner one blocks.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let executor = Rc::new(Executor {
+        tasks: RefCell::new(HashMap::new()),
+        next_id: Cell::new(MAIN + 1),
+        queue: Arc::new(ReadyQueue {
+            ready: Mutex::new(VecDeque::new()),
+            thread: thread::current(),
+        }),
+    });
+    CURRENT.with(|current| {
+        let mut current = current.borrow_mut();
+        assert!(
+            current.is_none(),
+            "block_on cannot be called from within block_on"
+        );
+        *current = Some(Rc::clone(&executor));
+    });
+    // Unsets `CURRENT` and drops the remaining tasks, also when a task panics.
+    struct Exit;
+    impl Drop for Exit {
+        fn drop(&mut self) {
+            let executor = CURRENT.with(|current| current.borrow_mut().take());
+            if let Some(executor) = executor {
+                // Dropped outside the borrow of `CURRENT`, in case a task's
+                // `Drop` looks at it.
+                drop(executor.tasks.take());
+            }
+        }
+    }
+    let _exit = Exit;
+
+    let mut future = Box::pin(future);
+    let main_waker = TaskWaker::new(MAIN, &executor.queue);
+    main_waker.wake_by_ref();
+    let waker = Waker::from(Arc::clone(&main_waker));
+    loop {
+        while let Some(id) = executor.queue.pop() {
+            if id != MAIN {
+                executor.poll_task(id);
+                continue;
+            }
+            main_waker.queued.store(false, Ordering::Release);
+            if let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker)) {
+                return output;
+            }
+        }
+        // Nothing is ready. `park` may also return spuriously, which only
+        // means another look at the empty queue.
+        thread::park();
+    }
+}
+
+/// Starts `future` as a new task of the executor of the current `block_on`.
+///
+/// The task runs concurrently with the future that spawned it, though never in
+/// parallel: whenever one of them waits, the others get to run. The returned
+/// `JoinHandle` is a future for the task's output; dropping it lets the task
+/// run on unobserved.
+///
+/// # Panics
+///
+/// Panics if it is called outside `block_on`.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+{
+    let executor = CURRENT
+        .with(|current| current.borrow().clone())
+        .expect("spawn must be called from within block_on");
+    let shared = Rc::new(RefCell::new(JoinState {
+        output: None,
+        finished: false,
+        waker: None,
+    }));
+    let task = {
+        let shared = Rc::clone(&shared);
+        async move {
+            let output = future.await;
+            let waker = {
+                let mut shared = shared.borrow_mut();
+                shared.output = Some(output);
+                shared.finished = true;
+                shared.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    };
+    executor.spawn(Box::pin(task));
+    JoinHandle { shared }
+}
+
+/// What a task and its `JoinHandle` share.
+struct JoinState<T> {
+    /// Set when the task has finished, until the `JoinHandle` takes it.
+    output: Option<T>,
+    /// Set when the task has finished, and never cleared.
+    finished: bool,
+    /// The waker of whoever awaits the `JoinHandle`.
+    waker: Option<Waker>,
+}
+
+/// A future for the output of a task started with `spawn`.
+pub struct JoinHandle<T> {
+    shared: Rc<RefCell<JoinState<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Returns `true` if the task has finished.
+    pub fn is_finished(&self) -> bool {
+        self.shared.borrow().finished
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut shared = self.shared.borrow_mut();
+        match shared.output.take() {
+            Some(output) => Poll::Ready(output),
+            None => {
+                // Only the latest waker counts, as the handle may have moved to
+                // another task since it was last polled.
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Gives the other ready tasks a turn, see `yield_now`.
+pub struct YieldNow {
+    yielded: bool,
+}
+
+/// Returns a future that lets the other ready tasks run before the current
+/// task continues: it wakes itself up and returns `Pending` once, which puts
+/// the task at the back of the ready queue.
+pub fn yield_now() -> YieldNow {
+    YieldNow { yielded: false }
+}
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.yielded {
+            return Poll::Ready(());
+        }
+        self.yielded = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future;
+    use std::panic::{self, AssertUnwindSafe};
+
+    #[test]
+    fn waking_a_task_several_times_polls_it_once_more() {
+        let polls = Rc::new(Cell::new(0));
+        let release = Rc::new(Cell::new(false));
+        let waker = Rc::new(RefCell::new(None));
+        let task = {
+            let (polls, release, waker) =
+                (Rc::clone(&polls), Rc::clone(&release), Rc::clone(&waker));
+            future::poll_fn(move |cx| {
+                polls.set(polls.get() + 1);
+                if release.get() {
+                    return Poll::Ready(());
+                }
+                if polls.get() == 1 {
+                    for _ in 0..3 {
+                        cx.waker().wake_by_ref();
+                    }
+                }
+                *waker.borrow_mut() = Some(cx.waker().clone());
+                Poll::Pending
+            })
+        };
+        block_on(async {
+            let handle = spawn(task);
+            // Long enough for the task to be polled for every extra wake-up.
+            for _ in 0..4 {
+                yield_now().await;
+            }
+            assert_eq!(polls.get(), 2);
+            release.set(true);
+            waker.borrow_mut().take().unwrap().wake();
+            handle.await;
+        });
+        assert_eq!(polls.get(), 3);
+    }
+
+    #[test]
+    fn a_stale_waker_of_a_finished_task_is_ignored() {
+        let stale = Rc::new(RefCell::new(None));
+        let task = {
+            let stale = Rc::clone(&stale);
+            future::poll_fn(move |cx| {
+                *stale.borrow_mut() = Some(cx.waker().clone());
+                Poll::Ready(7)
+            })
+        };
+        let output = block_on(async {
+            let output = spawn(task).await;
+            let waker: Waker = stale.borrow_mut().take().unwrap();
+            // Woken up from another thread, long after the task has finished.
+            thread::spawn(move || waker.wake()).join().unwrap();
+            yield_now().await;
+            output
+        });
+        assert_eq!(output, 7);
+    }
+
+    #[test]
+    fn block_on_within_block_on_panics() {
+        let payload = panic::catch_unwind(|| block_on(async { block_on(async {}) })).unwrap_err();
+        assert_eq!(
+            payload.downcast_ref::<&str>(),
+            Some(&"block_on cannot be called from within block_on")
+        );
+        // The outer `block_on` cleaned up after the panic, so it can be used again.
+        assert_eq!(block_on(async { 1 }), 1);
+    }
+
+    #[test]
+    fn a_panicking_task_unwinds_out_of_block_on() {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            block_on(async {
+                spawn(async { panic!("task failed") });
+                future::pending::<()>().await;
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(block_on(async { 2 }), 2);
+    }
+
+    #[test]
+    fn a_join_handle_can_be_awaited_from_another_task() {
+        let output = block_on(async {
+            let producer = spawn(async {
+                yield_now().await;
+                yield_now().await;
+                42
+            });
+            let mut producer = Box::pin(producer);
+            // Polled here first, so that the handle remembers this waker...
+            future::poll_fn(|cx| {
+                assert!(producer.as_mut().poll(cx).is_pending());
+                Poll::Ready(())
+            })
+            .await;
+            // ...which must not be the one woken up when the producer finishes.
+            let consumer = spawn(async move {
+                assert!(!producer.is_finished());
+                let output = producer.as_mut().await;
+                assert!(producer.is_finished());
+                output
+            });
+            consumer.await
+        });
+        assert_eq!(output, 42);
+    }
+}
@@ -1 +1,2 @@
 // Futures/async await library root
+pub mod executor;
@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures_async_await::executor::{block_on, spawn, yield_now};
+
+/// A timer future. The first poll starts a thread that sleeps until the
+/// deadline and then calls the waker, the way an I/O driver would wake a task
+/// when its socket becomes readable.
+struct Delay {
+    deadline: Instant,
+    /// The waker of the latest poll, shared with the timer thread once started.
+    waker: Option<Arc<Mutex<Waker>>>,
+    /// How often the future was polled, to show that nobody polls it in a loop.
+    polls: Rc<RefCell<u32>>,
+}
+
+fn delay(duration: Duration) -> Delay {
+    Delay {
+        deadline: Instant::now() + duration,
+        waker: None,
+        polls: Rc::new(RefCell::new(0)),
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        *self.polls.borrow_mut() += 1;
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        match &self.waker {
+            // The task may have moved since the last poll, so update the waker.
+            Some(waker) => *waker.lock().unwrap() = cx.waker().clone(),
+            None => {
+                let waker = Arc::new(Mutex::new(cx.waker().clone()));
+                let deadline = self.deadline;
+                self.waker = Some(Arc::clone(&waker));
+                thread::spawn(move || {
+                    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    waker.lock().unwrap().wake_by_ref();
+                });
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Runs a single future to completion, and shows that waiting for a timer
+/// costs no polls beyond the first one and the one after the wake-up.
+fn block_on_example() {
+    assert_eq!(block_on(async { 1 + 2 }), 3);
+
+    let timer = delay(Duration::from_millis(50));
+    let polls = Rc::clone(&timer.polls);
+    let start = Instant::now();
+    block_on(timer);
+    let elapsed = start.elapsed();
+    println!(
+        "Waited {:?} for a 50ms timer, polling it {} times",
+        elapsed,
+        polls.borrow()
+    );
+    assert!(elapsed >= Duration::from_millis(50));
+    assert_eq!(*polls.borrow(), 2);
+}
+
+/// Spawns tasks that wait for timers of different lengths. They all wait at
+/// the same time on the one thread, so the whole run takes about as long as
+/// the longest timer, and they finish in the order of their timers.
+fn spawn_example() {
+    let start = Instant::now();
+    let finished = block_on(async {
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let handles: Vec<_> = [300, 100, 200]
+            .into_iter()
+            .map(|ms| {
+                let order = Rc::clone(&order);
+                spawn(async move {
+                    delay(Duration::from_millis(ms)).await;
+                    order.borrow_mut().push(ms);
+                    ms * 2
+                })
+            })
+            .collect();
+        let mut results = Vec::new();
+        for handle in handles {
+            results.push(handle.await);
+        }
+        assert_eq!(results, [600, 200, 400]);
+        let order = order.borrow().clone();
+        order
+    });
+    let elapsed = start.elapsed();
+    println!(
+        "Tasks finished in the order {:?}, after {:?}",
+        finished, elapsed
+    );
+    assert_eq!(finished, [100, 200, 300]);
+    assert!(elapsed < Duration::from_millis(600));
+
+    // A task can spawn tasks of its own, and its handle can be awaited by
+    // another task than the one that spawned it.
+    let answer = block_on(async {
+        let inner = spawn(async { spawn(async { 20 }).await * 2 });
+        spawn(async move { inner.await + 2 }).await
+    });
+    println!("Nested tasks computed {}", answer);
+    assert_eq!(answer, 42);
+
+    // A handle still knows that its task finished after handing out the output.
+    block_on(async {
+        let mut handle = spawn(async { 7 });
+        assert!(!handle.is_finished());
+        assert_eq!((&mut handle).await, 7);
+        assert!(handle.is_finished());
+    });
+}
+
+/// Two tasks that never wait for anything take turns by yielding.
+fn yield_now_example() {
+    let log = block_on(async {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let tasks: Vec<_> = ["ping", "pong"]
+            .into_iter()
+            .map(|name| {
+                let log = Rc::clone(&log);
+                spawn(async move {
+                    for round in 0..3 {
+                        log.borrow_mut().push(format!("{} {}", name, round));
+                        yield_now().await;
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await;
+        }
+        let log = log.borrow().clone();
+        log
+    });
+    println!("Yielding tasks ran as: {}", log.join(", "));
+    assert_eq!(
+        log,
+        ["ping 0", "pong 0", "ping 1", "pong 1", "ping 2", "pong 2"]
+    );
+}
+
+fn main() {
+    println!("\n--- Running block_on Example ---");
+    block_on_example();
+    println!("\n--- Running spawn Example ---");
+    spawn_example();
+    println!("\n--- Running yield_now Example ---");
+    yield_now_example();
+}
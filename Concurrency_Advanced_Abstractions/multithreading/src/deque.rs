@@ -0,0 +1,362 @@
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{fence, AtomicIsize, AtomicPtr, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The capacity of a new deque. It doubles whenever the deque is full.
+const MIN_CAPACITY: usize = 64;
+
+/// A circular array of slots. Indices wrap around, so `top` and `bottom` can
+/// keep growing without ever being reset.
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+impl<T> Buffer<T> {
+    fn alloc(capacity: usize) -> *mut Buffer<T> {
+        debug_assert!(capacity.is_power_of_two());
+        let slots = (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect();
+        Box::into_raw(Box::new(Buffer { slots }))
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn at(&self, index: isize) -> *mut MaybeUninit<T> {
+        self.slots[index as usize & (self.capacity() - 1)].get()
+    }
+
+    /// Safety: Only the owner writes, and only to slots between `bottom` and
+    /// `top + capacity`, which no thief reads from.
+    unsafe fn write(&self, index: isize, value: T) {
+        unsafe { self.at(index).write(MaybeUninit::new(value)) };
+    }
+
+    /// Returns a bitwise copy of the slot. Thieves may copy a slot that another
+    /// thread is taking at the same time; only the winner of the race on `top`
+    /// may treat the copy as initialized, everyone else must forget it.
+    unsafe fn read(&self, index: isize) -> MaybeUninit<T> {
+        unsafe { ptr::read_volatile(self.at(index)) }
+    }
+}
+
+/// The state shared by the owner of a deque and its thieves.
+struct Inner<T> {
+    /// The next slot a thief steals from. Only ever increases.
+    top: AtomicIsize,
+    /// The slot the owner pushes to next.
+    bottom: AtomicIsize,
+    buffer: AtomicPtr<Buffer<T>>,
+    /// Buffers that were replaced by bigger ones. A thief may still be reading
+    /// from one of them, so they are only freed together with the deque.
+    retired: Mutex<Vec<*mut Buffer<T>>>,
+}
+
+/// The owner's end of a work-stealing deque (Chase and Lev).
+///
+/// The owner pushes and pops at the bottom, like a stack, so it keeps working
+/// on the task it created last, whose data is most likely still in its cache.
+/// Other threads steal from the top through a `Stealer`, taking the oldest
+/// task, which in divide-and-conquer code tends to be the biggest one. Owner and
+/// thieves only compete for the last item, so the common case needs no
+/// compare-and-swap at all.
+pub struct Worker<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// A handle for other threads to steal items from the top of a deque.
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+/// The outcome of `Stealer::steal`.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Steal<T> {
+    /// The deque was empty.
+    Empty,
+    /// We got the oldest item.
+    Success(T),
+    /// We lost a race for the item with another thread. Trying again may succeed.
+    Retry,
+}
+
+unsafe impl<T: Send> Send for Worker<T> {}
+unsafe impl<T: Send> Send for Stealer<T> {}
+unsafe impl<T: Send> Sync for Stealer<T> {}
+
+/// Creates a deque and returns its owner's end and a first stealer.
+pub fn deque<T>() -> (Worker<T>, Stealer<T>) {
+    let inner = Arc::new(Inner {
+        top: AtomicIsize::new(0),
+        bottom: AtomicIsize::new(0),
+        buffer: AtomicPtr::new(Buffer::alloc(MIN_CAPACITY)),
+        retired: Mutex::new(Vec::new()),
+    });
+    (
+        Worker {
+            inner: Arc::clone(&inner),
+        },
+        Stealer { inner },
+    )
+}
+
+impl<T> Worker<T> {
+    /// Pushes an item to the bottom of the deque.
+    pub fn push(&self, value: T) {
+        let inner = &*self.inner;
+        let bottom = inner.bottom.load(Ordering::Relaxed);
+        let top = inner.top.load(Ordering::Acquire);
+        let mut buffer = inner.buffer.load(Ordering::Relaxed);
+
+        // Safety: Only the owner replaces the buffer, so it is still alive.
+        if bottom - top >= unsafe { (*buffer).capacity() } as isize {
+            buffer = self.grow(top, bottom);
+        }
+        // Safety: The slot at `bottom` is not visible to thieves yet.
+        unsafe { (*buffer).write(bottom, value) };
+        // Publishes the item before thieves can see the new `bottom`.
+        fence(Ordering::Release);
+        inner.bottom.store(bottom + 1, Ordering::Relaxed);
+    }
+
+    /// Pops the item that was pushed last, unless it has been stolen.
+    pub fn pop(&self) -> Option<T> {
+        let inner = &*self.inner;
+        let bottom = inner.bottom.load(Ordering::Relaxed) - 1;
+        let buffer = inner.buffer.load(Ordering::Relaxed);
+        // Claim the bottom slot first, then look at `top`. The fence makes sure
+        // that a thief either sees our claim or we see its steal.
+        inner.bottom.store(bottom, Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+        let top = inner.top.load(Ordering::Relaxed);
+
+        if top > bottom {
+            // The deque was empty.
+            inner.bottom.store(bottom + 1, Ordering::Relaxed);
+            return None;
+        }
+
+        // Safety: The buffer is ours, and the slot holds an item.
+        let value = unsafe { (*buffer).read(bottom) };
+        if top == bottom {
+            // The last item: thieves may be going for it as well.
+            let won = inner
+                .top
+                .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok();
+            inner.bottom.store(bottom + 1, Ordering::Relaxed);
+            if !won {
+                // A thief took it; our copy must not be used or dropped.
+                return None;
+            }
+        }
+        // Safety: We own the item now.
+        Some(unsafe { value.assume_init() })
+    }
+
+    /// Returns `true` if the deque holds no items.
+    pub fn is_empty(&self) -> bool {
+        let bottom = self.inner.bottom.load(Ordering::Relaxed);
+        let top = self.inner.top.load(Ordering::Relaxed);
+        bottom <= top
+    }
+
+    /// Creates another handle for stealing from this deque.
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+
+    /// Moves the items to a buffer twice as big and returns it.
+    #[cold]
+    fn grow(&self, top: isize, bottom: isize) -> *mut Buffer<T> {
+        let inner = &*self.inner;
+        let old = inner.buffer.load(Ordering::Relaxed);
+        // Safety: Only the owner replaces the buffer, so it is still alive.
+        let new = Buffer::alloc(unsafe { (*old).capacity() } * 2);
+        for index in top..bottom {
+            // Safety: These slots hold the items, and the new buffer is ours.
+            // Copying an item that a thief is stealing right now is fine: only
+            // one of the two copies is ever used.
+            unsafe { (*new).at(index).write((*old).read(index)) };
+        }
+        // `Release`: thieves that load the new buffer see the copied items.
+        inner.buffer.store(new, Ordering::Release);
+        inner.retired.lock().unwrap().push(old);
+        new
+    }
+}
+
+impl<T> Stealer<T> {
+    /// Steals the oldest item from the top of the deque.
+    pub fn steal(&self) -> Steal<T> {
+        let inner = &*self.inner;
+        let top = inner.top.load(Ordering::Acquire);
+        // Pairs with the fence in `Worker::pop`.
+        fence(Ordering::SeqCst);
+        let bottom = inner.bottom.load(Ordering::Acquire);
+        if top >= bottom {
+            return Steal::Empty;
+        }
+
+        let buffer = inner.buffer.load(Ordering::Acquire);
+        // Safety: Buffers are only freed with the deque, which we keep alive.
+        let value = unsafe { (*buffer).read(top) };
+        if inner
+            .top
+            .compare_exchange(top, top + 1, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            // Someone else took the item; our copy must not be used or dropped.
+            return Steal::Retry;
+        }
+        // Safety: We won the race, so the item is ours.
+        Steal::Success(unsafe { value.assume_init() })
+    }
+
+    /// Returns `true` if the deque holds no items.
+    pub fn is_empty(&self) -> bool {
+        let top = self.inner.top.load(Ordering::Acquire);
+        let bottom = self.inner.bottom.load(Ordering::Acquire);
+        bottom <= top
+    }
+}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Stealer {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+/// Drops the items that are left and frees all buffers.
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let top = *self.top.get_mut();
+        let bottom = *self.bottom.get_mut();
+        let buffer = *self.buffer.get_mut();
+        // Safety: Nobody else has access any more.
+        unsafe {
+            for index in top..bottom {
+                (*(*buffer).at(index)).assume_init_drop();
+            }
+            drop(Box::from_raw(buffer));
+            for old in self.retired.get_mut().unwrap().drain(..) {
+                drop(Box::from_raw(old));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicUsize};
+    use std::thread;
+
+    /// An item that counts its own drops, to catch items that are lost or
+    /// dropped twice.
+    struct DropCounter {
+        id: usize,
+        drops: Arc<AtomicUsize>,
+    }
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn owner_pops_newest_and_thieves_steal_oldest() {
+        let (worker, stealer) = deque();
+        assert!(worker.is_empty() && stealer.is_empty());
+        for i in 1..=3 {
+            worker.push(i);
+        }
+        assert_eq!(stealer.steal(), Steal::Success(1));
+        assert_eq!(worker.pop(), Some(3));
+        assert_eq!(worker.pop(), Some(2));
+        assert_eq!(worker.pop(), None);
+        assert_eq!(stealer.steal(), Steal::Empty);
+    }
+
+    /// The owner pushes far more items than fit into the first buffer and pops
+    /// some of them, while three thieves steal. Every item must come out
+    /// exactly once and be dropped exactly once.
+    #[test]
+    fn concurrent_push_pop_steal_across_growth() {
+        const ITEMS: usize = 100_000;
+        let drops = Arc::new(AtomicUsize::new(0));
+        let done = AtomicBool::new(false);
+        let (worker, stealer) = deque::<DropCounter>();
+
+        let mut received = thread::scope(|s| {
+            let thieves: Vec<_> = (0..3)
+                .map(|_| {
+                    let (stealer, done) = (stealer.clone(), &done);
+                    s.spawn(move || {
+                        let mut stolen = Vec::new();
+                        loop {
+                            match stealer.steal() {
+                                Steal::Success(item) => stolen.push(item.id),
+                                Steal::Retry => {}
+                                Steal::Empty if done.load(Ordering::Acquire) => break,
+                                Steal::Empty => thread::yield_now(),
+                            }
+                        }
+                        stolen
+                    })
+                })
+                .collect();
+
+            let mut popped = Vec::new();
+            for id in 0..ITEMS {
+                worker.push(DropCounter {
+                    id,
+                    drops: Arc::clone(&drops),
+                });
+                if id % 3 == 0 {
+                    popped.extend(worker.pop().map(|item| item.id));
+                }
+            }
+            while let Some(item) = worker.pop() {
+                popped.push(item.id);
+            }
+            done.store(true, Ordering::Release);
+
+            for thief in thieves {
+                popped.extend(thief.join().unwrap());
+            }
+            popped
+        });
+
+        received.sort_unstable();
+        assert!(received.into_iter().eq(0..ITEMS));
+        assert_eq!(drops.load(Ordering::Relaxed), ITEMS);
+    }
+
+    #[test]
+    fn leftover_items_are_dropped_with_the_deque() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (worker, stealer) = deque::<DropCounter>();
+        for id in 0..1_000 {
+            worker.push(DropCounter {
+                id,
+                drops: Arc::clone(&drops),
+            });
+        }
+        assert!(matches!(stealer.steal(), Steal::Success(item) if item.id == 0));
+        assert_eq!(worker.pop().map(|item| item.id), Some(999));
+        drop(worker);
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+        drop(stealer);
+        assert_eq!(drops.load(Ordering::Relaxed), 1_000);
+    }
+}
@@ -1 +1,8 @@
 // Multithreading library root
+pub mod deque;
+pub mod pariter;
+pub mod parsort;
+#[cfg(test)]
+mod testutil;
+pub mod threadpool;
+pub mod workstealing;
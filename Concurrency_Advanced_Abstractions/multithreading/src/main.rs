@@ -0,0 +1,237 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use multithreading::pariter::{ParallelIterator, ParallelSlice};
+use multithreading::parsort::ParallelSliceMut;
+use multithreading::threadpool::ThreadPool;
+use multithreading::workstealing::{current_num_threads, join, WorkStealingPool};
+
+/// Runs more jobs than there are workers, collects results through join
+/// handles, and shows that a panicking job does not take its worker down.
+fn thread_pool_example() {
+    let pool = ThreadPool::new(4);
+
+    // `spawn` hands back the result of each job, like `thread::spawn` does.
+    let handles: Vec<_> = (1..=10_u64)
+        .map(|n| pool.spawn(move || (1..=n).product::<u64>()))
+        .collect();
+    let factorials: Vec<u64> = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect();
+    println!("Factorials: {:?}", factorials);
+    assert_eq!(factorials[9], 3_628_800);
+
+    // The panic is reported to whoever joins this job, and only to them.
+    let failed = pool.spawn(|| -> u32 { panic!("this job fails") });
+    let payload = failed.join().unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"this job fails"));
+    // Every worker is still there: jobs that take a while end up on all of them.
+    let names: Vec<_> = (0..pool.size())
+        .map(|_| {
+            pool.spawn(|| {
+                thread::sleep(Duration::from_millis(50));
+                thread::current().name().unwrap().to_string()
+            })
+        })
+        .collect();
+    let mut names: Vec<String> = names.into_iter().map(|name| name.join().unwrap()).collect();
+    names.sort();
+    println!("Workers still running: {:?}", names);
+    assert_eq!(names.len(), pool.size());
+    names.dedup();
+    assert_eq!(names.len(), pool.size());
+
+    // Dropping the pool waits for the jobs that are still queued.
+    let done = Arc::new(AtomicUsize::new(0));
+    for _ in 0..8 {
+        let done = Arc::clone(&done);
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(20));
+            done.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    drop(pool);
+    println!(
+        "Jobs finished before shutdown: {}",
+        done.load(Ordering::Relaxed)
+    );
+    assert_eq!(done.load(Ordering::Relaxed), 8);
+}
+
+/// Below this many numbers, splitting the work costs more than it gains.
+const SEQUENTIAL_CUTOFF: u64 = 10_000;
+
+/// The factorial from the functions lesson, as a product of a range. Computed
+/// modulo a prime, because the real thing overflows a `u64` already at 21!.
+fn factorial_mod(low: u64, high: u64) -> u64 {
+    const PRIME: u64 = 1_000_000_007;
+    if high - low <= SEQUENTIAL_CUTOFF {
+        return (low..high).fold(1, |product, n| product * (n % PRIME) % PRIME);
+    }
+    // Divide and conquer: both halves may run on different cores.
+    let middle = low + (high - low) / 2;
+    let (left, right) = join(
+        || factorial_mod(low, middle),
+        || factorial_mod(middle, high),
+    );
+    left * right % PRIME
+}
+
+fn sum(numbers: &[u64]) -> u64 {
+    if numbers.len() as u64 <= SEQUENTIAL_CUTOFF {
+        return numbers.iter().sum();
+    }
+    let (left, right) = numbers.split_at(numbers.len() / 2);
+    let (left, right) = join(|| sum(left), || sum(right));
+    left + right
+}
+
+/// Runs the recursive workloads on pools of different sizes. On a machine with
+/// several cores, the time drops as workers are added.
+fn work_stealing_example() {
+    let n = 20_000_000;
+    let numbers: Vec<u64> = (0..n).collect();
+    let expected_sum = n * (n - 1) / 2;
+    let expected_factorial = factorial_mod(1, 1_000);
+
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut sizes = vec![1, 2, cores];
+    sizes.sort();
+    sizes.dedup();
+    for size in sizes {
+        let pool = WorkStealingPool::new(size);
+        let start = Instant::now();
+        let (total, factorial) = pool.install(|| join(|| sum(&numbers), || factorial_mod(1, n)));
+        println!(
+            "{} worker(s): sum and factorial of {} numbers in {:?}",
+            size,
+            n,
+            start.elapsed()
+        );
+        assert_eq!(total, expected_sum);
+        // The same computation, split differently, must give the same result.
+        assert_eq!(pool.install(|| factorial_mod(1, 1_000)), expected_factorial);
+        assert_ne!(factorial, 0);
+    }
+
+    // A panic in either half reaches the caller of `join`, once both are done.
+    let pool = WorkStealingPool::new(2);
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        pool.join(
+            || sum(&numbers),
+            || -> u64 { panic!("the right half fails") },
+        )
+    }));
+    assert!(result.is_err());
+    // The pool is still usable afterwards, and so is the global one.
+    assert_eq!(pool.install(|| sum(&numbers)), expected_sum);
+    assert_eq!(join(|| 1, || 2), (1, 2));
+}
+
+/// `calculate_stats` from the variable experiments, with `iter` changed to `par_iter`.
+fn calculate_stats(numbers: &[i32]) -> (i32, i32, f64) {
+    let sum: i32 = numbers.par_iter().sum();
+    let count = numbers.len() as i32;
+    let average = sum as f64 / count as f64;
+    (sum, count, average)
+}
+
+/// Runs the pipelines from the lessons in parallel, and checks that they give
+/// exactly the same results as the sequential versions.
+fn par_iter_example() {
+    let numbers = vec![1, 2, 3, 4, 5];
+    // The same pipelines as in the functions lesson, with a one-word change.
+    let doubled_numbers: Vec<i32> = numbers.par_iter().map(|n| n * 2).collect();
+    println!("Doubled numbers: {:?}", doubled_numbers);
+    assert_eq!(doubled_numbers, [2, 4, 6, 8, 10]);
+    let filtered_numbers: Vec<i32> = numbers
+        .par_iter()
+        .filter(|&n| n % 2 == 0)
+        .map(|&n| n * 2)
+        .collect();
+    println!("Filtered and doubled numbers: {:?}", filtered_numbers);
+    assert_eq!(filtered_numbers, [4, 8]);
+    println!("Stats: {:?}", calculate_stats(&numbers));
+    assert_eq!(calculate_stats(&numbers), (15, 5, 3.0));
+
+    // Something big enough to be worth splitting up. `collect` keeps the order.
+    let big: Vec<u64> = (0..5_000_000).collect();
+    let slow_square = |n: &u64| (0..20).fold(*n, |acc, _| acc.wrapping_mul(31) ^ n) % 1_000;
+    let start = Instant::now();
+    let sequential: Vec<u64> = big.iter().map(slow_square).collect();
+    let sequential_time = start.elapsed();
+    let start = Instant::now();
+    let parallel: Vec<u64> = big.par_iter().map(slow_square).collect();
+    println!(
+        "Mapping {} numbers: {:?} sequential, {:?} parallel on {} worker(s)",
+        big.len(),
+        sequential_time,
+        start.elapsed(),
+        current_num_threads()
+    );
+    assert_eq!(parallel, sequential);
+
+    let evens = big
+        .par_iter()
+        .filter(|&&n| n % 2 == 0)
+        .map(|&n| n)
+        .sum::<u64>();
+    assert_eq!(evens, big.iter().filter(|&&n| n % 2 == 0).sum::<u64>());
+    let largest = big.par_iter().map(|&n| n).reduce(|| 0, u64::max);
+    assert_eq!(largest, 4_999_999);
+
+    let visited = AtomicUsize::new(0);
+    big.par_iter().for_each(|_| {
+        visited.fetch_add(1, Ordering::Relaxed);
+    });
+    assert_eq!(visited.into_inner(), big.len());
+}
+
+/// A small xorshift generator, so the sort checks need no extra crates.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Times the parallel sort against `slice::sort`. The parallel searches and
+/// the sort are checked against their `std` counterparts in the tests of
+/// `pariter` and `parsort`.
+fn par_sort_example() {
+    let mut rng = XorShift(0x2545_F491_4F6C_DD1D);
+    let mut numbers: Vec<u64> = (0..5_000_000).map(|_| rng.next()).collect();
+    let mut expected = numbers.clone();
+    let start = Instant::now();
+    expected.sort();
+    let sequential_time = start.elapsed();
+    let start = Instant::now();
+    numbers.par_sort();
+    println!(
+        "Sorting {} numbers: {:?} sequential, {:?} parallel on {} worker(s)",
+        numbers.len(),
+        sequential_time,
+        start.elapsed(),
+        current_num_threads()
+    );
+    assert_eq!(numbers, expected);
+}
+
+fn main() {
+    println!("--- Running Thread Pool Example ---");
+    thread_pool_example();
+    println!("\n--- Running Work-Stealing Example ---");
+    work_stealing_example();
+    println!("\n--- Running Parallel Iterator Example ---");
+    par_iter_example();
+    println!("\n--- Running Parallel Sort Example ---");
+    par_sort_example();
+}
@@ -0,0 +1,541 @@
+use std::cmp::Ordering;
+use std::collections::LinkedList;
+use std::iter::Sum;
+use std::marker::PhantomData;
+
+use crate::workstealing::{current_num_threads, join_context};
+
+/// Below this length, `par_binary_search` searches on one thread.
+const SEARCH_CUTOFF: usize = 1 << 16;
+
+/// The sequential work that a parallel iterator does on each piece of its input,
+/// plus how to combine the results of two neighbouring pieces.
+///
+/// Every terminal operation of `ParallelIterator` is a `Folder`, and every
+/// adapter wraps the folder it is given. A new operation can be added by
+/// implementing this trait and passing it to `ParallelIterator::drive`.
+pub trait Folder<T>: Sync {
+    type Result: Send;
+
+    /// Processes the items of one piece, in order.
+    fn consume_iter<I: Iterator<Item = T>>(&self, iter: I) -> Self::Result;
+
+    /// Combines the result of a piece with that of the piece right after it.
+    fn reduce(&self, left: Self::Result, right: Self::Result) -> Self::Result;
+}
+
+/// An iterator whose items are processed on the work-stealing pool.
+///
+/// It splits its input in halves with `join_context` until there are enough
+/// pieces to keep every worker busy, runs the pipeline sequentially on each
+/// piece, and combines the results in their original order.
+pub trait ParallelIterator: Sized + Send {
+    type Item: Send;
+
+    /// Runs `folder` on the pieces of the input and combines the results.
+    fn drive<C: Folder<Self::Item>>(self, folder: &C) -> C::Result;
+
+    /// Applies `map` to every item.
+    fn map<F, R>(self, map: F) -> Map<Self, F>
+    where
+        F: Fn(Self::Item) -> R + Sync + Send,
+        R: Send,
+    {
+        Map { base: self, map }
+    }
+
+    /// Keeps only the items for which `predicate` returns `true`.
+    fn filter<P>(self, predicate: P) -> Filter<Self, P>
+    where
+        P: Fn(&Self::Item) -> bool + Sync + Send,
+    {
+        Filter {
+            base: self,
+            predicate,
+        }
+    }
+
+    /// Calls `f` on every item, in no particular order.
+    fn for_each<F>(self, f: F)
+    where
+        F: Fn(Self::Item) + Sync,
+    {
+        self.drive(&ForEachFolder(f))
+    }
+
+    /// Combines all items with `op`. Each piece starts from `identity()`, so
+    /// `op` must be associative and `identity()` must not change the result.
+    fn reduce<ID, OP>(self, identity: ID, op: OP) -> Self::Item
+    where
+        ID: Fn() -> Self::Item + Sync,
+        OP: Fn(Self::Item, Self::Item) -> Self::Item + Sync,
+    {
+        self.drive(&ReduceFolder { identity, op })
+    }
+
+    /// Adds up all items.
+    fn sum<S>(self) -> S
+    where
+        S: Sum<Self::Item> + Sum<S> + Send,
+    {
+        self.drive(&SumFolder(PhantomData))
+    }
+
+    /// Collects the items into a collection, in their original order.
+    fn collect<C>(self) -> C
+    where
+        C: FromIterator<Self::Item>,
+    {
+        // Each piece collects into a `Vec`; linking them up keeps the order
+        // without copying anything until the final collection.
+        let pieces: LinkedList<Vec<Self::Item>> = self.drive(&CollectFolder);
+        pieces.into_iter().flatten().collect()
+    }
+}
+
+/// Adds `par_iter` and other parallel operations to slices, and through them
+/// to `Vec` and arrays.
+pub trait ParallelSlice<T: Sync> {
+    /// Returns a parallel iterator over references to the elements.
+    ///
+    /// A sequential pipeline such as `numbers.iter().map(|n| n * 2).collect()`
+    /// becomes parallel by changing `iter` to `par_iter`.
+    fn par_iter(&self) -> Iter<'_, T>;
+
+    /// Returns the largest element according to `compare`, or `None` if the
+    /// slice is empty. Of several equally large elements, the last one wins,
+    /// like with `Iterator::max_by`.
+    fn par_max_by<F>(&self, compare: F) -> Option<&T>
+    where
+        F: Fn(&T, &T) -> Ordering + Sync;
+
+    /// Searches a sorted slice for `x`, like `slice::binary_search`.
+    ///
+    /// Instead of halving the range in every step, it is cut into one more
+    /// piece than there are workers, and `x` is compared with all cut points
+    /// at once. That pays off for large slices with expensive comparisons.
+    /// With several matches, any one of them may be returned.
+    fn par_binary_search(&self, x: &T) -> Result<usize, usize>
+    where
+        T: Ord;
+}
+
+impl<T: Sync> ParallelSlice<T> for [T] {
+    fn par_iter(&self) -> Iter<'_, T> {
+        Iter { slice: self }
+    }
+
+    fn par_max_by<F>(&self, compare: F) -> Option<&T>
+    where
+        F: Fn(&T, &T) -> Ordering + Sync,
+    {
+        self.par_iter().map(Some).reduce(
+            || None,
+            |left, right| match (left, right) {
+                (Some(left), Some(right)) => match compare(left, right) {
+                    Ordering::Greater => Some(left),
+                    _ => Some(right),
+                },
+                (left, None) => left,
+                (None, right) => right,
+            },
+        )
+    }
+
+    fn par_binary_search(&self, x: &T) -> Result<usize, usize>
+    where
+        T: Ord,
+    {
+        // Every element before `low` is smaller than `x`, every element from
+        // `high` on is larger.
+        let (mut low, mut high) = (0, self.len());
+        let pieces = current_num_threads() + 1;
+        while pieces > 2 && high - low > SEARCH_CUTOFF {
+            let len = high - low;
+            let cuts: Vec<usize> = (1..pieces).map(|i| low + len * i / pieces).collect();
+            let (below, found) = cuts
+                .par_iter()
+                .map(|&cut| match self[cut].cmp(x) {
+                    Ordering::Less => (1, None),
+                    Ordering::Equal => (0, Some(cut)),
+                    Ordering::Greater => (0, None),
+                })
+                .reduce(
+                    || (0, None),
+                    |left, right| (left.0 + right.0, left.1.or(right.1)),
+                );
+            if let Some(index) = found {
+                return Ok(index);
+            }
+            // `x` belongs between the last cut below it and the first one above it.
+            if below > 0 {
+                low = cuts[below - 1] + 1;
+            }
+            if below < cuts.len() {
+                high = cuts[below];
+            }
+        }
+        match self[low..high].binary_search(x) {
+            Ok(index) => Ok(low + index),
+            Err(index) => Err(low + index),
+        }
+    }
+}
+
+/// A parallel iterator over a slice, see `ParallelSlice::par_iter`.
+pub struct Iter<'a, T> {
+    slice: &'a [T],
+}
+
+impl<'a, T: Sync> ParallelIterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn drive<C: Folder<Self::Item>>(self, folder: &C) -> C::Result {
+        bridge(self.slice, Splitter::new(), folder, false)
+    }
+}
+
+/// Decides how often to split, adapting to how busy the pool is.
+///
+/// We start out with one split per worker. Whenever a piece is stolen, some
+/// worker ran out of work, so the thief gets a fresh budget of splits. Pieces
+/// that stay with their worker stop splitting once the budget is used up, which
+/// keeps the number of jobs low when all workers are busy anyway.
+#[derive(Clone, Copy)]
+struct Splitter {
+    splits: usize,
+}
+
+impl Splitter {
+    fn new() -> Self {
+        Splitter {
+            splits: current_num_threads(),
+        }
+    }
+
+    fn try_split(&mut self, migrated: bool) -> bool {
+        if migrated {
+            self.splits = current_num_threads().max(self.splits / 2);
+            true
+        } else if self.splits > 0 {
+            self.splits /= 2;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn bridge<'a, T, C>(slice: &'a [T], mut splitter: Splitter, folder: &C, migrated: bool) -> C::Result
+where
+    T: Sync,
+    C: Folder<&'a T>,
+{
+    if slice.len() > 1 && splitter.try_split(migrated) {
+        let (left, right) = slice.split_at(slice.len() / 2);
+        let (left, right) = join_context(
+            |context| bridge(left, splitter, folder, context.migrated()),
+            |context| bridge(right, splitter, folder, context.migrated()),
+        );
+        folder.reduce(left, right)
+    } else {
+        folder.consume_iter(slice.iter())
+    }
+}
+
+/// A parallel iterator that maps items, see `ParallelIterator::map`.
+pub struct Map<I, F> {
+    base: I,
+    map: F,
+}
+
+impl<I, F, R> ParallelIterator for Map<I, F>
+where
+    I: ParallelIterator,
+    F: Fn(I::Item) -> R + Sync + Send,
+    R: Send,
+{
+    type Item = R;
+
+    fn drive<C: Folder<R>>(self, folder: &C) -> C::Result {
+        let Map { base, map } = self;
+        base.drive(&MapFolder { map: &map, folder })
+    }
+}
+
+struct MapFolder<'f, F, C> {
+    map: &'f F,
+    folder: &'f C,
+}
+
+impl<T, F, R, C> Folder<T> for MapFolder<'_, F, C>
+where
+    F: Fn(T) -> R + Sync,
+    C: Folder<R>,
+{
+    type Result = C::Result;
+
+    fn consume_iter<I: Iterator<Item = T>>(&self, iter: I) -> C::Result {
+        self.folder.consume_iter(iter.map(self.map))
+    }
+
+    fn reduce(&self, left: C::Result, right: C::Result) -> C::Result {
+        self.folder.reduce(left, right)
+    }
+}
+
+/// A parallel iterator that filters items, see `ParallelIterator::filter`.
+pub struct Filter<I, P> {
+    base: I,
+    predicate: P,
+}
+
+impl<I, P> ParallelIterator for Filter<I, P>
+where
+    I: ParallelIterator,
+    P: Fn(&I::Item) -> bool + Sync + Send,
+{
+    type Item = I::Item;
+
+    fn drive<C: Folder<I::Item>>(self, folder: &C) -> C::Result {
+        let Filter { base, predicate } = self;
+        base.drive(&FilterFolder {
+            predicate: &predicate,
+            folder,
+        })
+    }
+}
+
+struct FilterFolder<'f, P, C> {
+    predicate: &'f P,
+    folder: &'f C,
+}
+
+impl<T, P, C> Folder<T> for FilterFolder<'_, P, C>
+where
+    P: Fn(&T) -> bool + Sync,
+    C: Folder<T>,
+{
+    type Result = C::Result;
+
+    fn consume_iter<I: Iterator<Item = T>>(&self, iter: I) -> C::Result {
+        self.folder.consume_iter(iter.filter(self.predicate))
+    }
+
+    fn reduce(&self, left: C::Result, right: C::Result) -> C::Result {
+        self.folder.reduce(left, right)
+    }
+}
+
+struct ForEachFolder<F>(F);
+
+impl<T, F: Fn(T) + Sync> Folder<T> for ForEachFolder<F> {
+    type Result = ();
+
+    fn consume_iter<I: Iterator<Item = T>>(&self, iter: I) {
+        iter.for_each(&self.0);
+    }
+
+    fn reduce(&self, _left: (), _right: ()) {}
+}
+
+struct ReduceFolder<ID, OP> {
+    identity: ID,
+    op: OP,
+}
+
+impl<T, ID, OP> Folder<T> for ReduceFolder<ID, OP>
+where
+    T: Send,
+    ID: Fn() -> T + Sync,
+    OP: Fn(T, T) -> T + Sync,
+{
+    type Result = T;
+
+    fn consume_iter<I: Iterator<Item = T>>(&self, iter: I) -> T {
+        iter.fold((self.identity)(), &self.op)
+    }
+
+    fn reduce(&self, left: T, right: T) -> T {
+        (self.op)(left, right)
+    }
+}
+
+/// `fn() -> S` makes the folder `Sync` whatever `S` is; it never holds an `S`.
+struct SumFolder<S>(PhantomData<fn() -> S>);
+
+impl<T, S> Folder<T> for SumFolder<S>
+where
+    S: Sum<T> + Sum<S> + Send,
+{
+    type Result = S;
+
+    fn consume_iter<I: Iterator<Item = T>>(&self, iter: I) -> S {
+        iter.sum()
+    }
+
+    fn reduce(&self, left: S, right: S) -> S {
+        [left, right].into_iter().sum()
+    }
+}
+
+struct CollectFolder;
+
+impl<T: Send> Folder<T> for CollectFolder {
+    type Result = LinkedList<Vec<T>>;
+
+    fn consume_iter<I: Iterator<Item = T>>(&self, iter: I) -> Self::Result {
+        LinkedList::from([iter.collect()])
+    }
+
+    fn reduce(&self, mut left: Self::Result, mut right: Self::Result) -> Self::Result {
+        left.append(&mut right);
+        left
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::XorShift;
+    use crate::workstealing::WorkStealingPool;
+
+    /// Counts the pieces that `bridge` cut the input into.
+    struct PieceCounter;
+
+    impl<T> Folder<T> for PieceCounter {
+        type Result = usize;
+
+        fn consume_iter<I: Iterator<Item = T>>(&self, iter: I) -> usize {
+            iter.for_each(drop);
+            1
+        }
+
+        fn reduce(&self, left: usize, right: usize) -> usize {
+            left + right
+        }
+    }
+
+    #[test]
+    fn adapters_match_the_sequential_iterator() {
+        let numbers: Vec<u64> = (0..1_000_000).map(|n| n * 7919 % 1_000_003).collect();
+        WorkStealingPool::new(4).install(|| {
+            let parallel: Vec<u64> = numbers.par_iter().map(|n| n * 2).collect();
+            let sequential: Vec<u64> = numbers.iter().map(|n| n * 2).collect();
+            assert_eq!(parallel, sequential);
+
+            let parallel: Vec<&u64> = numbers.par_iter().filter(|&&n| n % 3 == 0).collect();
+            let sequential: Vec<&u64> = numbers.iter().filter(|&&n| n % 3 == 0).collect();
+            assert_eq!(parallel, sequential);
+
+            let parallel: u64 = numbers.par_iter().sum();
+            assert_eq!(parallel, numbers.iter().sum::<u64>());
+
+            let parallel = numbers.par_iter().map(|&n| n).reduce(|| 0, u64::max);
+            assert_eq!(parallel, *numbers.iter().max().unwrap());
+
+            // `reduce` combines neighbouring pieces in order, so an associative
+            // but not commutative operation gives the sequential result.
+            let parallel =
+                numbers
+                    .par_iter()
+                    .map(|&n| vec![n])
+                    .reduce(Vec::new, |mut left, right| {
+                        left.extend(right);
+                        left
+                    });
+            assert_eq!(parallel, numbers);
+        });
+    }
+
+    #[test]
+    fn collect_keeps_the_order_on_small_and_empty_inputs() {
+        WorkStealingPool::new(4).install(|| {
+            for len in 0..20 {
+                let numbers: Vec<usize> = (0..len).collect();
+                let collected: Vec<usize> = numbers.par_iter().map(|&n| n).collect();
+                assert_eq!(collected, numbers);
+            }
+        });
+    }
+
+    #[test]
+    fn splitter_budget_and_migration() {
+        WorkStealingPool::new(4).install(|| {
+            let mut splitter = Splitter::new();
+            assert_eq!(splitter.splits, 4);
+            // Without steals, the budget halves with every split.
+            assert!(splitter.try_split(false));
+            assert!(splitter.try_split(false));
+            assert!(splitter.try_split(false));
+            assert!(!splitter.try_split(false));
+            // A stolen piece gets a fresh budget of one split per worker.
+            assert!(splitter.try_split(true));
+            assert_eq!(splitter.splits, 4);
+        });
+    }
+
+    #[test]
+    fn pieces_follow_the_number_of_workers() {
+        let numbers = vec![0u8; 10_000];
+        // A single worker never steals: one split, two pieces.
+        let pieces = WorkStealingPool::new(1).install(|| numbers.par_iter().drive(&PieceCounter));
+        assert_eq!(pieces, 2);
+        // Four workers get at least three levels of splits, more if pieces are
+        // stolen.
+        let pieces = WorkStealingPool::new(4).install(|| numbers.par_iter().drive(&PieceCounter));
+        assert!(pieces >= 8, "only {pieces} pieces");
+    }
+
+    #[test]
+    fn par_max_by_matches_max_by_on_random_inputs() {
+        let mut rng = XorShift(0x2545_F491_4F6C_DD1D);
+        WorkStealingPool::new(4).install(|| {
+            for len in [0, 1, 2, 100, 5_000, 100_000] {
+                // With few and with many duplicates. Comparing by the value alone
+                // shows which of several equal maxima wins: the last one.
+                for range in [10, u64::MAX] {
+                    let input: Vec<(u64, usize)> =
+                        (0..len).map(|index| (rng.next() % range, index)).collect();
+                    assert_eq!(
+                        input.par_max_by(|a, b| a.0.cmp(&b.0)),
+                        input.iter().max_by(|a, b| a.0.cmp(&b.0))
+                    );
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn par_binary_search_matches_binary_search_on_random_inputs() {
+        let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+        WorkStealingPool::new(4).install(|| {
+            // Five pieces per step, so the large inputs go through the cut
+            // points several times before they get below `SEARCH_CUTOFF`.
+            assert_eq!(current_num_threads(), 4);
+            for len in [0, 1, 2, 100, SEARCH_CUTOFF + 1, 1_000_000] {
+                for range in [10, 1_000, u64::MAX] {
+                    let mut sorted: Vec<u64> = (0..len).map(|_| rng.next() % range).collect();
+                    sorted.sort_unstable();
+                    for _ in 0..200 {
+                        let x = rng.next() % range;
+                        match (sorted.par_binary_search(&x), sorted.binary_search(&x)) {
+                            // With duplicates, both may find a different copy of `x`.
+                            (Ok(found), Ok(_)) => assert_eq!(sorted[found], x),
+                            (found, expected) => assert_eq!(found, expected),
+                        }
+                    }
+                    // Below the smallest and above the largest element.
+                    if let (Some(&first), Some(&last)) = (sorted.first(), sorted.last()) {
+                        if first > 0 {
+                            assert_eq!(sorted.par_binary_search(&(first - 1)), Err(0));
+                        }
+                        if last < u64::MAX {
+                            assert_eq!(sorted.par_binary_search(&(last + 1)), Err(len));
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,140 @@
+use std::cmp::Ordering;
+
+use crate::workstealing::join;
+
+/// Below this length, a slice is sorted on one thread.
+const SEQUENTIAL_CUTOFF: usize = 4096;
+
+/// Adds parallel sorting to slices, and through them to `Vec` and arrays.
+///
+/// All sorts are stable: equal elements keep their order, just like with
+/// `slice::sort`.
+pub trait ParallelSliceMut<T: Send> {
+    /// Sorts the slice in parallel.
+    fn par_sort(&mut self)
+    where
+        T: Ord;
+
+    /// Sorts the slice in parallel, comparing elements with `compare`.
+    fn par_sort_by<F>(&mut self, compare: F)
+    where
+        F: Fn(&T, &T) -> Ordering + Sync;
+
+    /// Sorts the slice in parallel, by the key that `f` extracts.
+    fn par_sort_by_key<K, F>(&mut self, f: F)
+    where
+        K: Ord,
+        F: Fn(&T) -> K + Sync;
+}
+
+impl<T: Send> ParallelSliceMut<T> for [T] {
+    fn par_sort(&mut self)
+    where
+        T: Ord,
+    {
+        merge_sort(self, &T::cmp);
+    }
+
+    fn par_sort_by<F>(&mut self, compare: F)
+    where
+        F: Fn(&T, &T) -> Ordering + Sync,
+    {
+        merge_sort(self, &compare);
+    }
+
+    fn par_sort_by_key<K, F>(&mut self, f: F)
+    where
+        K: Ord,
+        F: Fn(&T) -> K + Sync,
+    {
+        merge_sort(self, &|a: &T, b: &T| f(a).cmp(&f(b)));
+    }
+}
+
+/// A merge sort whose two halves are sorted in parallel.
+///
+/// Merging is left to the stable sort of the standard library. It spots that
+/// the slice consists of two sorted runs and merges them in linear time, so we
+/// need no unsafe code to move elements around. Merges at the same depth run
+/// in parallel; only the final one uses a single thread.
+fn merge_sort<T, F>(v: &mut [T], compare: &F)
+where
+    T: Send,
+    F: Fn(&T, &T) -> Ordering + Sync,
+{
+    if v.len() <= SEQUENTIAL_CUTOFF {
+        v.sort_by(compare);
+        return;
+    }
+    let (left, right) = v.split_at_mut(v.len() / 2);
+    join(|| merge_sort(left, compare), || merge_sort(right, compare));
+    v.sort_by(compare);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::XorShift;
+
+    /// Checks `par_sort` against `slice::sort`, and that `par_sort_by_key` is
+    /// stable: equal keys keep the order they had in the input.
+    fn check(input: Vec<u64>) {
+        let mut expected = input.clone();
+        expected.sort();
+        let mut sorted = input.clone();
+        sorted.par_sort();
+        assert_eq!(sorted, expected);
+
+        let mut pairs: Vec<(u64, usize)> = input
+            .iter()
+            .enumerate()
+            .map(|(index, &n)| (n % 7, index))
+            .collect();
+        let mut expected = pairs.clone();
+        expected.sort_by_key(|pair| pair.0);
+        pairs.par_sort_by_key(|pair| pair.0);
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn random() {
+        let mut rng = XorShift(0x2545_F491_4F6C_DD1D);
+        for len in [2, 100, SEQUENTIAL_CUTOFF + 1, 100_000, 300_000] {
+            // With few and with many duplicates.
+            for range in [10, u64::MAX] {
+                check((0..len).map(|_| rng.next() % range).collect());
+            }
+        }
+    }
+
+    #[test]
+    fn empty() {
+        check(Vec::new());
+    }
+
+    #[test]
+    fn single_element() {
+        check(vec![42]);
+    }
+
+    #[test]
+    fn already_sorted() {
+        check((0..100_000).collect());
+        check((0..100_000).rev().collect());
+    }
+
+    #[test]
+    fn all_equal() {
+        check(vec![7; 100_000]);
+    }
+
+    #[test]
+    fn by_comparator() {
+        let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+        let mut numbers: Vec<u64> = (0..100_000).map(|_| rng.next()).collect();
+        let mut expected = numbers.clone();
+        expected.sort_by(|a, b| b.cmp(a));
+        numbers.par_sort_by(|a, b| b.cmp(a));
+        assert_eq!(numbers, expected);
+    }
+}
@@ -0,0 +1,131 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A unit of work for the pool: any closure that can run once on another thread.
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of worker threads that run jobs from a shared queue.
+///
+/// Spawning an OS thread for every small task is expensive. A pool starts its
+/// threads once and then hands them one job after another. The queue is an
+/// `mpsc` channel whose receiving end the workers share behind a `Mutex`: each
+/// idle worker waits for the lock, takes the next job and releases the lock
+/// again before running it.
+///
+/// A job that panics only aborts that job; its worker catches the panic and
+/// moves on to the next one. Dropping the pool closes the queue and waits for
+/// the workers, which first finish every job that is still queued.
+pub struct ThreadPool {
+    workers: Vec<thread::JoinHandle<()>>,
+    /// `None` once the pool is shutting down.
+    sender: Option<Sender<Job>>,
+}
+
+/// A handle to the result of a job started with `ThreadPool::spawn`.
+pub struct JoinHandle<T> {
+    result: Receiver<thread::Result<T>>,
+}
+
+impl ThreadPool {
+    /// Creates a pool with `size` worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn new(size: usize) -> ThreadPool {
+        assert!(size > 0, "a thread pool needs at least one worker");
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = (0..size)
+            .map(|id| {
+                let receiver = Arc::clone(&receiver);
+                thread::Builder::new()
+                    .name(format!("pool-worker-{}", id))
+                    .spawn(move || worker_loop(&receiver))
+                    .expect("failed to spawn a worker thread")
+            })
+            .collect();
+
+        ThreadPool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    /// Returns the number of worker threads.
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Queues a job that runs on the next idle worker.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.sender
+            .as_ref()
+            .expect("the pool only closes its queue when it is dropped")
+            .send(Box::new(f))
+            .expect("the workers outlive the pool's sender");
+    }
+
+    /// Queues a job and returns a handle to wait for its result.
+    ///
+    /// Like `std::thread::spawn`, joining the handle gives the value the job
+    /// returned, or the payload it panicked with.
+    pub fn spawn<F, T>(&self, f: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (sender, result) = mpsc::channel();
+        self.execute(move || {
+            let outcome = panic::catch_unwind(AssertUnwindSafe(f));
+            // The handle may have been dropped already; nobody wants the result then.
+            let _ = sender.send(outcome);
+        });
+        JoinHandle { result }
+    }
+}
+
+impl<T> JoinHandle<T> {
+    /// Waits for the job to finish and returns its result.
+    pub fn join(self) -> thread::Result<T> {
+        self.result
+            .recv()
+            .expect("the pool runs every queued job before shutting down")
+    }
+}
+
+/// Takes jobs from the queue and runs them until the queue is closed and empty.
+fn worker_loop(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // The guard is a temporary of this statement, so the lock is released
+        // before the job runs and other workers can take the next one.
+        let message = receiver.lock().unwrap().recv();
+        match message {
+            // The default panic hook has already reported the panic, and a
+            // `spawn`ed job passes it on to its handle. The worker carries on.
+            Ok(job) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(job));
+            }
+            // The pool has been dropped and every queued job is done.
+            Err(_) => break,
+        }
+    }
+}
+
+/// Shuts the pool down gracefully: jobs that are still queued run first.
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        // Closing the queue makes `recv` fail once it is empty.
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
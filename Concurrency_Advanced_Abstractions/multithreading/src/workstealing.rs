@@ -0,0 +1,564 @@
+use std::any::Any;
+use std::cell::{Cell, UnsafeCell};
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+
+use crate::deque::{self, Steal, Stealer, Worker};
+
+/// A type-erased pointer to a job, which lives on the stack of the thread that
+/// waits for it. That thread does not return before the job has run, so the
+/// pointer stays valid for as long as the job can be found in a queue.
+#[derive(Clone, Copy)]
+struct JobRef {
+    pointer: *const (),
+    execute_fn: unsafe fn(*const ()),
+}
+
+// Safety: `join` and `install` only create jobs whose closures are `Send`.
+unsafe impl Send for JobRef {}
+
+impl JobRef {
+    /// Safety: The job must not have run yet.
+    unsafe fn execute(self) {
+        unsafe { (self.execute_fn)(self.pointer) }
+    }
+}
+
+/// Signals that a job is done.
+trait Latch {
+    /// The last thing a job does: the waiting thread may free the job right after.
+    fn set(&self);
+}
+
+/// A latch for pool workers, which keep running other jobs while they wait.
+struct SpinLatch(AtomicBool);
+
+impl SpinLatch {
+    fn new() -> Self {
+        SpinLatch(AtomicBool::new(false))
+    }
+
+    fn probe(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+impl Latch for SpinLatch {
+    fn set(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// A latch for threads outside the pool, which block until the job is done.
+struct LockLatch {
+    done: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl LockLatch {
+    fn new() -> Self {
+        LockLatch {
+            done: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+
+    fn wait(&self) {
+        let mut done = self.done.lock().unwrap();
+        while !*done {
+            done = self.condvar.wait(done).unwrap();
+        }
+    }
+}
+
+impl Latch for LockLatch {
+    fn set(&self) {
+        let mut done = self.done.lock().unwrap();
+        *done = true;
+        self.condvar.notify_all();
+    }
+}
+
+enum JobResult<R> {
+    None,
+    Ok(R),
+    Panic(Box<dyn Any + Send>),
+}
+
+/// A closure waiting to run, together with room for its result.
+struct StackJob<L, F, R> {
+    latch: L,
+    /// The worker that created the job, or null if it came from outside the pool.
+    origin: *const WorkerThread,
+    func: UnsafeCell<Option<F>>,
+    result: UnsafeCell<JobResult<R>>,
+}
+
+impl<L, F, R> StackJob<L, F, R>
+where
+    L: Latch,
+    F: FnOnce(FnContext) -> R + Send,
+    R: Send,
+{
+    fn new(latch: L, origin: *const WorkerThread, func: F) -> Self {
+        StackJob {
+            latch,
+            origin,
+            func: UnsafeCell::new(Some(func)),
+            result: UnsafeCell::new(JobResult::None),
+        }
+    }
+
+    /// Safety: The job must outlive every queue the `JobRef` is put in.
+    unsafe fn as_job_ref(&self) -> JobRef {
+        JobRef {
+            pointer: (self as *const Self).cast(),
+            execute_fn: Self::execute,
+        }
+    }
+
+    /// Runs the closure on whichever thread found the job, and stores its
+    /// result or panic for the waiting thread.
+    unsafe fn execute(this: *const ()) {
+        // Safety: `this` comes from `as_job_ref`, and only one thread executes a job.
+        let this = unsafe { &*this.cast::<Self>() };
+        let func = unsafe { (*this.func.get()).take() }.expect("a job runs only once");
+        let context = FnContext {
+            migrated: !ptr::eq(WORKER.with(Cell::get), this.origin),
+        };
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| func(context))) {
+            Ok(value) => JobResult::Ok(value),
+            Err(payload) => JobResult::Panic(payload),
+        };
+        unsafe { *this.result.get() = result };
+        this.latch.set();
+    }
+
+    /// Runs the closure right here, for a job that nobody has stolen.
+    fn run_inline(self) -> R {
+        let func = self.func.into_inner().expect("a job runs only once");
+        func(FnContext { migrated: false })
+    }
+
+    /// Returns the result of the job, or resumes its panic.
+    fn into_result(self) -> R {
+        match self.result.into_inner() {
+            JobResult::Ok(value) => value,
+            JobResult::Panic(payload) => panic::resume_unwind(payload),
+            JobResult::None => unreachable!("the latch is only set after the job has run"),
+        }
+    }
+}
+
+/// Lets idle workers block instead of spinning, without missing new jobs.
+struct Sleep {
+    lock: Mutex<()>,
+    condvar: Condvar,
+    sleepers: AtomicUsize,
+    /// Bumped for every new job. A worker only goes to sleep if it has not
+    /// changed since the worker last looked for work.
+    events: AtomicUsize,
+}
+
+impl Sleep {
+    /// Tells the workers that there is a new job, waking one if they all sleep.
+    fn announce(&self) {
+        self.events.fetch_add(1, Ordering::SeqCst);
+        // Pairs with the increment in `sleep`: either we see the sleeper, or it
+        // sees our event and stays awake.
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.condvar.notify_one();
+        }
+    }
+
+    fn wake_all(&self) {
+        self.events.fetch_add(1, Ordering::SeqCst);
+        let _guard = self.lock.lock().unwrap();
+        self.condvar.notify_all();
+    }
+
+    /// Blocks until the next event, unless there has been one since `seen`.
+    fn sleep(&self, seen: usize) {
+        let guard = self.lock.lock().unwrap();
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        if self.events.load(Ordering::SeqCst) == seen {
+            // We hold the lock until `wait` releases it, so `announce` cannot
+            // notify in between and leave us sleeping.
+            drop(self.condvar.wait(guard).unwrap());
+        }
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// The state that all workers of a pool share.
+struct Registry {
+    stealers: Vec<Stealer<JobRef>>,
+    /// Jobs from threads outside the pool, see `WorkStealingPool::install`.
+    injector: Mutex<VecDeque<JobRef>>,
+    sleep: Sleep,
+    terminate: AtomicBool,
+}
+
+/// One worker of a pool. It lives on the stack of its thread.
+struct WorkerThread {
+    deque: Worker<JobRef>,
+    index: usize,
+    registry: Arc<Registry>,
+    /// State of the xorshift generator that picks victims to steal from.
+    rng: Cell<u64>,
+}
+
+thread_local! {
+    /// The worker running on the current thread, or null outside of any pool.
+    static WORKER: Cell<*const WorkerThread> = const { Cell::new(ptr::null()) };
+}
+
+impl WorkerThread {
+    /// Returns the worker running on the current thread, if there is one.
+    fn current<'a>() -> Option<&'a WorkerThread> {
+        // Safety: A worker clears the pointer before it goes away.
+        unsafe { WORKER.with(Cell::get).as_ref() }
+    }
+
+    fn push(&self, job: JobRef) {
+        self.deque.push(job);
+        self.registry.sleep.announce();
+    }
+
+    /// Looks for a job: first our own newest one, then the oldest one of a
+    /// random other worker, then one from outside the pool.
+    fn find_work(&self) -> Option<JobRef> {
+        self.deque
+            .pop()
+            .or_else(|| self.steal())
+            .or_else(|| self.registry.injector.lock().unwrap().pop_front())
+    }
+
+    fn steal(&self) -> Option<JobRef> {
+        let stealers = &self.registry.stealers;
+        let count = stealers.len();
+        loop {
+            let mut retry = false;
+            // Starting at a random victim spreads thieves over all workers,
+            // instead of all of them hammering the first one.
+            let start = self.next_random() % count;
+            for victim in (start..count).chain(0..start) {
+                if victim == self.index {
+                    continue;
+                }
+                match stealers[victim].steal() {
+                    Steal::Success(job) => return Some(job),
+                    Steal::Retry => retry = true,
+                    Steal::Empty => {}
+                }
+            }
+            if !retry {
+                return None;
+            }
+        }
+    }
+
+    fn next_random(&self) -> usize {
+        let mut x = self.rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.set(x);
+        x as usize
+    }
+
+    /// Runs other jobs until `latch` is set.
+    fn wait_until(&self, latch: &SpinLatch) {
+        while !latch.probe() {
+            match self.find_work() {
+                // Safety: Every job in a queue is waiting to run.
+                Some(job) => unsafe { job.execute() },
+                // The job we wait for is running on another thread.
+                None => thread::yield_now(),
+            }
+        }
+    }
+
+    fn main_loop(&self) {
+        let sleep = &self.registry.sleep;
+        loop {
+            let seen = sleep.events.load(Ordering::SeqCst);
+            if let Some(job) = self.find_work() {
+                // Safety: Every job in a queue is waiting to run.
+                unsafe { job.execute() };
+                continue;
+            }
+            if self.registry.terminate.load(Ordering::SeqCst) {
+                break;
+            }
+            sleep.sleep(seen);
+        }
+    }
+}
+
+/// A pool of threads for fork-join parallelism, driven by `join`.
+///
+/// Every worker has its own deque of jobs instead of sharing one queue behind a
+/// lock like `ThreadPool` does. `join(a, b)` pushes `b` to the bottom of the
+/// current worker's deque and runs `a` right away. Idle workers steal from the
+/// top of a randomly chosen deque. If nobody has stolen `b` by the time `a` is
+/// done, the worker pops it and runs it itself; otherwise it helps out with
+/// other jobs until the thief is finished. In a recursive divide-and-conquer
+/// algorithm, thieves thus take the largest pieces of work, and most jobs never
+/// leave the worker that created them.
+pub struct WorkStealingPool {
+    registry: Arc<Registry>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl WorkStealingPool {
+    /// Creates a pool with `num_threads` worker threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `num_threads` is 0.
+    pub fn new(num_threads: usize) -> WorkStealingPool {
+        assert!(num_threads > 0, "a thread pool needs at least one worker");
+
+        let (deques, stealers): (Vec<_>, Vec<_>) = (0..num_threads).map(|_| deque::deque()).unzip();
+        let registry = Arc::new(Registry {
+            stealers,
+            injector: Mutex::new(VecDeque::new()),
+            sleep: Sleep {
+                lock: Mutex::new(()),
+                condvar: Condvar::new(),
+                sleepers: AtomicUsize::new(0),
+                events: AtomicUsize::new(0),
+            },
+            terminate: AtomicBool::new(false),
+        });
+
+        let threads = deques
+            .into_iter()
+            .enumerate()
+            .map(|(index, deque)| {
+                let registry = Arc::clone(&registry);
+                thread::Builder::new()
+                    .name(format!("steal-worker-{}", index))
+                    .spawn(move || {
+                        let worker = WorkerThread {
+                            deque,
+                            index,
+                            registry,
+                            rng: Cell::new(0x9E37_79B9_7F4A_7C15 ^ (index as u64 + 1)),
+                        };
+                        WORKER.with(|current| current.set(&worker));
+                        worker.main_loop();
+                        WORKER.with(|current| current.set(ptr::null()));
+                    })
+                    .expect("failed to spawn a worker thread")
+            })
+            .collect();
+
+        WorkStealingPool { registry, threads }
+    }
+
+    /// Returns the number of worker threads.
+    pub fn current_num_threads(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Runs `f` on one of the pool's workers and waits for its result, so that
+    /// the `join` calls inside of `f` use this pool.
+    ///
+    /// A panic in `f` is passed on to the caller.
+    pub fn install<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce() -> R + Send,
+        R: Send,
+    {
+        if let Some(worker) = WorkerThread::current() {
+            if Arc::ptr_eq(&worker.registry, &self.registry) {
+                return f();
+            }
+        }
+
+        let job = StackJob::new(LockLatch::new(), ptr::null(), |_| f());
+        // Safety: We wait for the job below, so it outlives the queue entry.
+        let job_ref = unsafe { job.as_job_ref() };
+        self.registry.injector.lock().unwrap().push_back(job_ref);
+        self.registry.sleep.announce();
+        job.latch.wait();
+        job.into_result()
+    }
+
+    /// Like the free function `join`, but runs on this pool.
+    pub fn join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        self.install(|| join(a, b))
+    }
+}
+
+/// Stops the workers. `install` borrows the pool, so no job can be left by now.
+impl Drop for WorkStealingPool {
+    fn drop(&mut self) {
+        self.registry.terminate.store(true, Ordering::SeqCst);
+        self.registry.sleep.wake_all();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Returns the number of workers of the pool that `join` runs on when called
+/// from the current thread.
+pub fn current_num_threads() -> usize {
+    match WorkerThread::current() {
+        Some(worker) => worker.registry.stealers.len(),
+        None => global_pool().current_num_threads(),
+    }
+}
+
+/// Returns the pool that `join` uses outside of `WorkStealingPool::install`,
+/// with one worker per core.
+fn global_pool() -> &'static WorkStealingPool {
+    static GLOBAL: OnceLock<WorkStealingPool> = OnceLock::new();
+    GLOBAL.get_or_init(|| {
+        let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        WorkStealingPool::new(threads)
+    })
+}
+
+/// Passed to the closures of `join_context`.
+#[derive(Clone, Copy, Debug)]
+pub struct FnContext {
+    migrated: bool,
+}
+
+impl FnContext {
+    /// Returns `true` if the closure runs on another thread than the one that
+    /// called `join_context`, which means that it was stolen. Work that gets
+    /// stolen is a sign of idle workers, so it may be worth splitting further.
+    pub fn migrated(&self) -> bool {
+        self.migrated
+    }
+}
+
+/// Runs `a` and `b`, potentially in parallel, and returns both results.
+///
+/// Inside a pool, `b` is offered to other workers while the current thread runs
+/// `a`. Called from any other thread, the pair runs on a global pool with one
+/// worker per core. If either closure panics, the panic is passed on once both
+/// closures have finished.
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    join_context(|_| a(), |_| b())
+}
+
+/// Like `join`, but the closures learn whether they were stolen.
+pub fn join_context<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce(FnContext) -> RA + Send,
+    B: FnOnce(FnContext) -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    let Some(worker) = WorkerThread::current() else {
+        return global_pool().install(|| join_context(a, b));
+    };
+
+    let job_b = StackJob::new(SpinLatch::new(), worker, b);
+    // Safety: We do not return before `b` has run, see below.
+    let job_b_ref = unsafe { job_b.as_job_ref() };
+    worker.push(job_b_ref);
+
+    // `b` points into this stack frame, so even if `a` panics we must not
+    // unwind before `b` is out of every queue.
+    let result_a = panic::catch_unwind(AssertUnwindSafe(|| a(FnContext { migrated: false })));
+
+    while !job_b.latch.probe() {
+        match worker.deque.pop() {
+            // Nobody stole `b`: run it ourselves.
+            Some(job) if job.pointer == job_b_ref.pointer => {
+                let result_b = job_b.run_inline();
+                return (unwrap_or_resume(result_a), result_b);
+            }
+            // `b` was stolen and this job was pushed before it. Run it while we wait.
+            // Safety: Every job in a queue is waiting to run.
+            Some(job) => unsafe { job.execute() },
+            // `b` was stolen: help out with other jobs until the thief is done.
+            None => worker.wait_until(&job_b.latch),
+        }
+    }
+    (unwrap_or_resume(result_a), job_b.into_result())
+}
+
+fn unwrap_or_resume<T>(result: thread::Result<T>) -> T {
+    result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fib(n: u64) -> u64 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = join(|| fib(n - 1), || fib(n - 2));
+        a + b
+    }
+
+    fn panic_message(payload: Box<dyn Any + Send>) -> &'static str {
+        *payload.downcast::<&'static str>().unwrap()
+    }
+
+    #[test]
+    fn join_passes_on_a_panic_from_either_side() {
+        let pool = WorkStealingPool::new(2);
+        let left = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.join(|| -> u64 { panic!("left") }, || fib(15))
+        }));
+        assert_eq!(panic_message(left.unwrap_err()), "left");
+        let right = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.join(|| fib(15), || -> u64 { panic!("right") })
+        }));
+        assert_eq!(panic_message(right.unwrap_err()), "right");
+        // The pool survives both.
+        assert_eq!(pool.install(|| fib(15)), 610);
+    }
+
+    #[test]
+    fn nested_install_and_join() {
+        let pool = WorkStealingPool::new(4);
+        let other = WorkStealingPool::new(2);
+        let (fib, (inner, workers)) = pool.install(|| {
+            assert_eq!(current_num_threads(), 4);
+            join(
+                || fib(20),
+                // Installing on the same pool from one of its workers just runs
+                // the closure; installing on another pool waits for that one.
+                || {
+                    pool.install(|| {
+                        let inner = pool.join(|| 1, || 2);
+                        (inner, other.install(current_num_threads))
+                    })
+                },
+            )
+        });
+        assert_eq!(fib, 6765);
+        assert_eq!(inner, (1, 2));
+        assert_eq!(workers, 2);
+    }
+}
@@ -1,7 +1,10 @@
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 
+use threads::sync::{MyArc, Weak};
+
 fn main() {
     // 1. Create data on the heap, wrapped in an Arc.
     // `Arc` stands for "Atomically-Referenced Counter". It's a smart pointer
@@ -41,4 +44,113 @@ fn main() {
         "[Main] Final reference count: {}",
         Arc::strong_count(&shared_data)
     );
+
+    println!("\n--- MyArc Example (get_mut, make_mut, try_unwrap) ---");
+    my_arc_example();
+
+    println!("\n--- MyArc Example (Parent/child cycle) ---");
+    parent_child_cycle();
+}
+
+/// Shows when `MyArc` hands out mutable access, and when it copies the value first.
+fn my_arc_example() {
+    let mut config = MyArc::new(vec![1, 2, 3]);
+
+    // The only reference: the value can be changed in place.
+    MyArc::get_mut(&mut config).unwrap().push(4);
+    println!("[Main] Changed in place: {:?}", config);
+
+    // A second `MyArc` (or a `Weak`) makes the value shared, so no `get_mut`.
+    let snapshot = MyArc::clone(&config);
+    assert!(MyArc::get_mut(&mut config).is_none());
+    println!(
+        "[Main] Shared by {} MyArcs, get_mut refuses",
+        MyArc::strong_count(&config)
+    );
+
+    // `make_mut` copies a shared value before changing it, so `snapshot` keeps the old one.
+    MyArc::make_mut(&mut config).push(5);
+    assert!(!MyArc::ptr_eq(&config, &snapshot));
+    assert_eq!(*snapshot, [1, 2, 3, 4]);
+    println!(
+        "[Main] After make_mut: {:?}, snapshot still {:?}",
+        config, snapshot
+    );
+
+    // Unique again: `make_mut` changes the value in place, without copying.
+    let before = &*config as *const Vec<i32>;
+    MyArc::make_mut(&mut config).push(6);
+    assert_eq!(&*config as *const Vec<i32>, before);
+
+    // `try_unwrap` only gives the value back to the last `MyArc`.
+    let snapshot_clone = MyArc::clone(&snapshot);
+    let snapshot = MyArc::try_unwrap(snapshot).unwrap_err();
+    drop(snapshot_clone);
+    let weak = MyArc::downgrade(&snapshot);
+    let value = MyArc::try_unwrap(snapshot).unwrap();
+    assert!(weak.upgrade().is_none());
+    println!(
+        "[Main] Unwrapped {:?}; the Weak can no longer upgrade",
+        value
+    );
+}
+
+/// A parent owns its children; each child points back to its parent.
+struct Parent {
+    name: &'static str,
+    children: Mutex<Vec<MyArc<Child>>>,
+}
+
+/// If `parent` were a `MyArc`, parent and children would keep each other alive
+/// forever. A `Weak` does not, so dropping the parent frees the whole tree.
+struct Child {
+    name: &'static str,
+    parent: Weak<Parent>,
+}
+
+impl Drop for Parent {
+    fn drop(&mut self) {
+        println!("[Drop] Parent {}", self.name);
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        println!("[Drop] Child {}", self.name);
+    }
+}
+
+fn parent_child_cycle() {
+    let parent = MyArc::new(Parent {
+        name: "root",
+        children: Mutex::new(Vec::new()),
+    });
+    for name in ["left", "right"] {
+        let child = MyArc::new(Child {
+            name,
+            parent: MyArc::downgrade(&parent),
+        });
+        parent.children.lock().unwrap().push(child);
+    }
+
+    // Only the parent's own `MyArc` is strong; the children hold `Weak`s.
+    println!(
+        "[Main] Parent has {} strong and {} weak references",
+        MyArc::strong_count(&parent),
+        MyArc::weak_count(&parent)
+    );
+
+    // Keep one child around after the parent is gone.
+    let left = MyArc::clone(&parent.children.lock().unwrap()[0]);
+    let parent_name = left.parent.upgrade().map(|parent| parent.name);
+    println!("[Main] Child {} has parent {:?}", left.name, parent_name);
+
+    drop(parent);
+    // The parent is gone and has dropped its `MyArc`s to the children;
+    // `right` had no other owner and went with it.
+    println!(
+        "[Main] Child {} outlived its parent, which can no longer be upgraded: {}",
+        left.name,
+        left.parent.upgrade().is_none()
+    );
 }
@@ -1,6 +1,241 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
-use std::thread;
 use std::time::Duration;
+use std::{env, fs, process, thread};
+
+use multithreading::threadpool::ThreadPool;
+use threads::atomicarc::AtomicArc;
+use threads::configcell::{ConfigCell, FileWatcher};
+use threads::seqlock::SeqLock;
+use threads::shardedmap::ShardedMap;
+
+/// A small configuration that can live in a `SeqLock`: all its fields are `Copy`.
+/// Every field is derived from `version`, so a torn (half-updated) copy is easy to spot.
+#[derive(Clone, Copy, PartialEq)]
+struct Limits {
+    version: u64,
+    max_connections: u64,
+    timeout_ms: u64,
+}
+
+impl Limits {
+    fn for_version(version: u64) -> Self {
+        Self {
+            version,
+            max_connections: version * 10,
+            timeout_ms: version * 100,
+        }
+    }
+
+    fn is_consistent(&self) -> bool {
+        *self == Self::for_version(self.version)
+    }
+}
+
+/// Lets one writer and `readers` reader threads share the configuration for a
+/// while. `read` and `write` decide which lock is used. Returns the number of
+/// reads and writes that were completed.
+fn readers_and_writer(
+    readers: usize,
+    read: impl Fn() -> Limits + Sync,
+    write: impl Fn(Limits) + Sync,
+) -> (u64, u64) {
+    let stop = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        let reader_handles: Vec<_> = (0..readers)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut reads = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        assert!(read().is_consistent());
+                        reads += 1;
+                    }
+                    reads
+                })
+            })
+            .collect();
+
+        // The writer updates the configuration now and then, like a config reload.
+        let writer_handle = s.spawn(|| {
+            let mut writes = 0;
+            while !stop.load(Ordering::Relaxed) {
+                writes += 1;
+                write(Limits::for_version(writes));
+                thread::sleep(Duration::from_micros(100));
+            }
+            writes
+        });
+
+        thread::sleep(Duration::from_millis(200));
+        stop.store(true, Ordering::Relaxed);
+
+        let reads = reader_handles.into_iter().map(|h| h.join().unwrap()).sum();
+        (reads, writer_handle.join().unwrap())
+    })
+}
+
+/// Compares `SeqLock` and `AtomicArc` with `RwLock` for 1 writer and a growing
+/// number of readers. `RwLock` readers all write to the lock's reader count;
+/// `SeqLock` readers never write, so they do not slow each other down.
+/// `AtomicArc` readers only touch the reference count of the current version.
+fn read_mostly_benchmark() {
+    println!(
+        "{:>8} {:>16} {:>16} {:>16}",
+        "readers", "RwLock reads", "SeqLock reads", "AtomicArc reads"
+    );
+    for readers in [1, 2, 4, 8] {
+        let rwlock = RwLock::new(Limits::for_version(0));
+        let (rwlock_reads, _) = readers_and_writer(
+            readers,
+            || *rwlock.read().unwrap(),
+            |limits| *rwlock.write().unwrap() = limits,
+        );
+
+        let seqlock = SeqLock::new(Limits::for_version(0));
+        let (seqlock_reads, _) =
+            readers_and_writer(readers, || seqlock.read(), |limits| seqlock.store(limits));
+
+        let atomic = AtomicArc::new(Arc::new(Limits::for_version(0)));
+        let (atomic_reads, _) = readers_and_writer(
+            readers,
+            || *atomic.load(),
+            |limits| atomic.store(Arc::new(limits)),
+        );
+
+        println!(
+            "{:>8} {:>16} {:>16} {:>16}",
+            readers, rwlock_reads, seqlock_reads, atomic_reads
+        );
+    }
+}
+
+/// Reads a config file of the form `version = N`.
+fn parse_limits(contents: &str) -> Option<Limits> {
+    let version = contents.trim().strip_prefix("version = ")?;
+    version.parse().ok().map(Limits::for_version)
+}
+
+/// Reloads the configuration from a file while reader threads keep using it.
+/// Unlike with the `RwLock` above, publishing a new version never blocks them.
+fn config_reload_example() {
+    let path = env::temp_dir().join(format!("shard-state-{}.conf", process::id()));
+    fs::write(&path, "version = 1\n").unwrap();
+
+    let config = Arc::new(ConfigCell::new(Limits::for_version(0)));
+    let reloads = config.subscribe();
+    let watcher = FileWatcher::new(
+        Arc::clone(&config),
+        &path,
+        Duration::from_millis(10),
+        parse_limits,
+    );
+
+    let stop = AtomicBool::new(false);
+    let reads = AtomicU64::new(0);
+    thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| {
+                while !stop.load(Ordering::Relaxed) {
+                    // Every snapshot is a whole version, never a mix of two.
+                    assert!(config.load().is_consistent());
+                    reads.fetch_add(1, Ordering::Relaxed);
+                    thread::yield_now();
+                }
+            });
+        }
+
+        let reload = || reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+        println!("Loaded config version {}", reload().version);
+
+        // A broken file keeps the last good version.
+        fs::write(&path, "version = oops\n").unwrap();
+        assert!(reloads.recv_timeout(Duration::from_millis(100)).is_err());
+        assert_eq!(config.load().version, 1);
+        println!("Ignored a broken config file, still on version 1");
+
+        for version in 2..=3 {
+            fs::write(&path, format!("version = {}\n", version)).unwrap();
+            let limits = reload();
+            assert_eq!(limits.version, version);
+            println!(
+                "Reloaded config version {} (max_connections = {}, timeout_ms = {})",
+                limits.version, limits.max_connections, limits.timeout_ms
+            );
+        }
+        stop.store(true, Ordering::Relaxed);
+    });
+
+    drop(watcher);
+    fs::remove_file(&path).unwrap();
+    println!(
+        "Readers took {} snapshots during the reloads",
+        reads.load(Ordering::Relaxed)
+    );
+}
+
+/// The number of distinct keys in the map benchmark.
+const KEYS: u64 = 1024;
+
+/// Lets `threads` threads look up and count keys in `map` for a while: 9 out of
+/// 10 operations are `get`s, the rest increment a counter through `entry`.
+/// Returns the number of operations that were completed.
+fn mixed_workload(map: &ShardedMap<u64, u64>, threads: usize) -> u64 {
+    let stop = AtomicBool::new(false);
+
+    let (operations, increments) = thread::scope(|s| {
+        let handles: Vec<_> = (0..threads as u64)
+            .map(|id| {
+                let stop = &stop;
+                s.spawn(move || {
+                    // A small xorshift generator, so every thread uses different keys.
+                    let mut seed = 0x9E37_79B9_7F4A_7C15 ^ (id + 1);
+                    let (mut operations, mut increments) = (0, 0);
+                    while !stop.load(Ordering::Relaxed) {
+                        seed ^= seed << 13;
+                        seed ^= seed >> 7;
+                        seed ^= seed << 17;
+                        let key = seed % KEYS;
+                        if seed % 10 == 0 {
+                            *map.entry(key).or_insert(0) += 1;
+                            increments += 1;
+                        } else {
+                            map.get(&key);
+                        }
+                        operations += 1;
+                    }
+                    (operations, increments)
+                })
+            })
+            .collect();
+
+        thread::sleep(Duration::from_millis(200));
+        stop.store(true, Ordering::Relaxed);
+
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .fold((0, 0), |(o, i), (ho, hi)| (o + ho, i + hi))
+    });
+
+    // No increment may get lost, whatever shard it landed in.
+    assert_eq!(map.iter().map(|(_, count)| count).sum::<u64>(), increments);
+    operations
+}
+
+/// Compares a `ShardedMap` with one shard, which is just a single global
+/// `RwLock<HashMap>`, against one with the default number of shards.
+fn sharded_map_vs_global_lock_benchmark() {
+    println!(
+        "{:>8} {:>16} {:>16}",
+        "threads", "global lock ops", "sharded ops"
+    );
+    for threads in [1, 2, 4, 8] {
+        let global = mixed_workload(&ShardedMap::with_shards(1), threads);
+        let sharded = mixed_workload(&ShardedMap::new(), threads);
+        println!("{:>8} {:>16} {:>16}", threads, global, sharded);
+    }
+}
 
 fn main() {
     // --- Shared Counter (using Arc<Mutex<T>>) ---
@@ -8,18 +243,20 @@ fn main() {
     // Mutex provides mutually exclusive access to the inner `u32` to prevent data races.
     let shared_counter = Arc::new(Mutex::new(0_u32));
     let mut counter_handles = vec![];
+    // Short jobs like these don't need a thread each: a pool of 3 workers runs all 5.
+    let pool = ThreadPool::new(3);
 
     println!("--- Mutex Example (Shared Counter) ---");
     for i in 0..5 {
-        // Clone the Arc to give each thread its own "shared ownership" pointer.
+        // Clone the Arc to give each job its own "shared ownership" pointer.
         let counter_for_thread = Arc::clone(&shared_counter);
-        let handle = thread::spawn(move || {
-            println!("Thread {} trying to acquire counter lock...", i);
+        let handle = pool.spawn(move || {
+            println!("Job {} trying to acquire counter lock...", i);
             // Acquire the lock. This call blocks if another thread holds the lock.
             // `unwrap()` is used for simplicity; in real code, handle errors.
             let mut num = counter_for_thread.lock().unwrap();
             *num += 1; // Mutate the shared data
-            println!("Thread {} incremented counter to: {}", i, *num);
+            println!("Job {} incremented counter to: {}", i, *num);
             thread::sleep(Duration::from_millis(50)); // Simulate some work
             // The `MutexGuard` (returned by `lock()`) is dropped here when `num` goes out of scope,
             // automatically releasing the lock.
@@ -27,7 +264,7 @@ fn main() {
         counter_handles.push(handle);
     }
 
-    // Wait for all counter threads to finish
+    // Wait for all counter jobs to finish
     for handle in counter_handles {
         handle.join().unwrap();
     }
@@ -35,8 +272,7 @@ fn main() {
     // Access the final value from the main thread
     let final_counter_value = *shared_counter.lock().unwrap();
     println!("Final counter value: {}", final_counter_value);
-    println!("");
-
+    println!();
 
     // --- Shared Configuration String (using Arc<RwLock<T>>) ---
     // RwLock allows multiple readers concurrently, but only one writer exclusively.
@@ -72,7 +308,6 @@ fn main() {
     });
     config_handles.push(writer_handle);
 
-
     // Another Reader Thread (will likely read the updated config)
     let config_late_reader_thread = Arc::clone(&shared_config);
     let late_reader_handle = thread::spawn(move || {
@@ -82,7 +317,6 @@ fn main() {
     });
     config_handles.push(late_reader_handle);
 
-
     // Wait for all config threads to finish
     for handle in config_handles {
         handle.join().unwrap();
@@ -91,4 +325,19 @@ fn main() {
     // Access the final config value from the main thread
     let final_config_value = shared_config.read().unwrap();
     println!("Final config value: \"{}\"", *final_config_value);
-}
\ No newline at end of file
+    println!();
+
+    // --- Read-mostly Configuration (SeqLock vs RwLock vs AtomicArc) ---
+    println!("--- SeqLock vs RwLock vs AtomicArc Benchmark (reads in 200ms) ---");
+    read_mostly_benchmark();
+    println!();
+
+    // --- Hot-reloaded Configuration (ConfigCell + FileWatcher) ---
+    println!("--- ConfigCell Example (Reload from a File) ---");
+    config_reload_example();
+    println!();
+
+    // --- Shared Map (ShardedMap vs one global lock) ---
+    println!("--- ShardedMap vs Global Lock Benchmark (operations in 200ms) ---");
+    sharded_map_vs_global_lock_benchmark();
+}
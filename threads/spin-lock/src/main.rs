@@ -1,39 +1,115 @@
 use std::sync::Arc;
 use std::thread;
+use std::time::Instant;
 
-mod spinlock;
-use spinlock::SpinLock;
+#[cfg(feature = "stats")]
+use threads::backoff::{Backoff, Spin};
+use threads::backoff::{Constant, Exponential, SpinThenPark, SpinThenYield};
+use threads::barrier::Barrier;
+use threads::lock::Lock;
+use threads::mcslock::McsLock;
+use threads::parkinglock::ParkingLock;
+use threads::spinlock::SpinLock;
+use threads::ticketlock::TicketLock;
 
-fn spinlock_example() {
-    let counter = Arc::new(SpinLock::new(0));
-    let mut handles = vec![];
+#[cfg(feature = "model")]
+mod model;
 
-    println!("Spinning up 10 threads to increment a counter 100,000 times each...");
+/// Runs the counter benchmark against any lock that implements the shared `Lock` API.
+/// Returns the lock so that callers can inspect it afterwards.
+fn spinlock_example<L>(name: &str) -> Arc<L>
+where
+    L: Lock<u64> + Send + Sync + 'static,
+{
+    let counter = Arc::new(L::new(0));
+    let mut handles = vec![];
+    // The workers and the main thread meet here, so that all threads start
+    // incrementing together instead of the first ones getting a head start
+    // while the others are still being spawned.
+    let start_line = Arc::new(Barrier::new(11));
 
+    println!(
+        "[{}] Spinning up 10 threads to increment a counter 100,000 times each...",
+        name
+    );
     for _ in 0..10 {
         let counter_clone = Arc::clone(&counter);
+        let start_line = Arc::clone(&start_line);
         let handle = thread::spawn(move || {
+            start_line.wait();
             for _ in 0..100_000 {
                 // The lock is acquired, the guard is created.
-                let mut guard = counter_clone.lock();
+                let mut guard = counter_clone.lock().unwrap();
                 // The value is incremented via DerefMut.
                 *guard += 1;
                 // The guard is dropped at the end of the scope, releasing the lock.
             }
+            // Each thread reports when it finished its share of the work.
+            Instant::now()
         });
         handles.push(handle);
     }
 
-    for handle in handles {
-        handle.join().unwrap();
-    }
+    // Every worker has been spawned: fire the starting gun.
+    start_line.wait();
+    let start = Instant::now();
 
-    let final_count = *counter.lock(); // Lock to read the final value.
-    println!("Final count: {}", final_count);
+    let finish_times: Vec<_> = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect();
+
+    let elapsed = start.elapsed();
+    let final_count = *counter.lock().unwrap(); // Lock to read the final value.
+    println!("[{}] Final count: {} in {:?}", name, final_count, elapsed);
+    // With a fair lock all threads progress at the same pace and finish close together.
+    // An unfair lock lets some threads race ahead while others starve.
+    let first = finish_times.iter().min().unwrap();
+    let last = finish_times.iter().max().unwrap();
+    println!(
+        "[{}] Spread between first and last thread to finish: {:?}",
+        name,
+        *last - *first
+    );
     assert_eq!(final_count, 1_000_000);
+    counter
+}
+
+/// Runs the counter benchmark for one backoff strategy and prints what the
+/// lock's contention counters recorded.
+#[cfg(feature = "stats")]
+fn contention_report<B: Backoff + 'static>(name: &str) {
+    let counter = spinlock_example::<SpinLock<u64, B>>(name);
+    println!("[{}] Contention report:\n{}", name, counter.stats());
 }
 
 fn main() {
     println!("\n--- Running SpinLock Example ---");
-    spinlock_example();
+    spinlock_example::<SpinLock<u64>>("SpinLock");
+    println!("\n--- Running TicketLock Example ---");
+    spinlock_example::<TicketLock<u64>>("TicketLock");
+    println!("\n--- Running McsLock Example ---");
+    spinlock_example::<McsLock<u64>>("McsLock");
+    println!("\n--- Running ParkingLock Example ---");
+    spinlock_example::<ParkingLock<u64>>("ParkingLock");
+
+    println!("\n--- Comparing SpinLock Backoff Strategies ---");
+    spinlock_example::<SpinLock<u64, Constant>>("SpinLock<Constant>");
+    spinlock_example::<SpinLock<u64, Exponential>>("SpinLock<Exponential>");
+    spinlock_example::<SpinLock<u64, SpinThenYield>>("SpinLock<SpinThenYield>");
+    spinlock_example::<SpinLock<u64, SpinThenPark>>("SpinLock<SpinThenPark>");
+
+    #[cfg(feature = "stats")]
+    {
+        println!("\n--- SpinLock Contention Reports ---");
+        contention_report::<Spin>("SpinLock<Spin>");
+        contention_report::<Exponential>("SpinLock<Exponential>");
+        contention_report::<SpinThenYield>("SpinLock<SpinThenYield>");
+    }
+
+    #[cfg(feature = "model")]
+    {
+        println!("\n--- Model Checking SpinLock ---");
+        model::model_check_examples();
+    }
 }
@@ -0,0 +1,136 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use threads::condvar::SpinCondvar;
+use threads::model::{self, AtomicBool, Checker, UnsafeCell};
+use threads::spinlock::SpinLock;
+
+/// Two threads increment a counter behind a `SpinLock`. The checker also
+/// verifies that the two increments never touch the counter at the same time.
+fn spinlock_counter() {
+    let lock = Arc::new(SpinLock::new(0));
+    let other = {
+        let lock = Arc::clone(&lock);
+        model::spawn(move || *lock.lock().unwrap() += 1)
+    };
+    *lock.lock().unwrap() += 1;
+    other.join().unwrap();
+    assert_eq!(*lock.lock().unwrap(), 2);
+}
+
+/// The producer-consumer queue of the `threads` examples, cut down to one
+/// producer and one consumer with two items.
+fn condvar_queue() {
+    let queue = Arc::new((SpinLock::new(VecDeque::new()), SpinCondvar::new()));
+    let producer = {
+        let queue = Arc::clone(&queue);
+        model::spawn(move || {
+            let (items, not_empty) = &*queue;
+            for item in 0..2 {
+                items.lock().unwrap().push_back(item);
+                not_empty.notify_one();
+            }
+        })
+    };
+    let (items, not_empty) = &*queue;
+    for expected in 0..2 {
+        let mut items = not_empty
+            .wait_while(items.lock().unwrap(), |items| items.is_empty())
+            .unwrap();
+        assert_eq!(items.pop_front(), Some(expected));
+    }
+    producer.join().unwrap();
+}
+
+/// A spinlock with a bug that no amount of stress testing on x86 will show:
+/// it releases the lock with `Relaxed` instead of `Release`, so the next owner
+/// is not guaranteed to see what the previous one wrote.
+struct RelaxedLock {
+    locked: AtomicBool,
+    value: UnsafeCell<u64>,
+}
+
+// Safety: Not really, that is the point; the model check finds out.
+unsafe impl Sync for RelaxedLock {}
+
+impl RelaxedLock {
+    fn increment(&self) {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            model::spin_loop();
+        }
+        // Safety: We hold the lock, or so we think.
+        unsafe { *self.value.get() += 1 };
+        self.locked.store(false, Ordering::Relaxed);
+    }
+}
+
+fn relaxed_lock_counter() {
+    let lock = Arc::new(RelaxedLock {
+        locked: AtomicBool::new(false),
+        value: UnsafeCell::new(0),
+    });
+    let other = {
+        let lock = Arc::clone(&lock);
+        model::spawn(move || lock.increment())
+    };
+    lock.increment();
+    other.join().unwrap();
+}
+
+/// Runs the model checks, and shows what a failure and its replay look like.
+pub fn model_check_examples() {
+    let checker = Checker::new();
+    let executions = checker.check(spinlock_counter).unwrap();
+    println!("[SpinLock] Counter checked in {executions} executions");
+    let executions = checker.check(condvar_queue).unwrap();
+    println!("[SpinLock + SpinCondvar] Queue checked in {executions} executions");
+
+    let failure = checker
+        .check(relaxed_lock_counter)
+        .expect_err("a Relaxed unlock must be caught");
+    println!("[RelaxedLock] {failure}");
+    let replayed = checker
+        .replay(failure.schedule(), relaxed_lock_counter)
+        .expect_err("the schedule must fail again");
+    assert_eq!(replayed.message(), failure.message());
+    println!("[RelaxedLock] Replaying the schedule:");
+    for step in replayed.trace() {
+        println!("[RelaxedLock]   {step}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spinlock_counter_passes() {
+        let executions = Checker::new().check(spinlock_counter).unwrap();
+        assert!(executions > 1);
+    }
+
+    #[test]
+    fn condvar_queue_passes() {
+        let executions = Checker::new().check(condvar_queue).unwrap();
+        assert!(executions > 1);
+    }
+
+    #[test]
+    fn relaxed_unlock_fails_and_replays() {
+        let checker = Checker::new();
+        let failure = checker.check(relaxed_lock_counter).unwrap_err();
+        assert!(!failure.schedule().is_empty());
+
+        let replayed = checker
+            .replay(failure.schedule(), relaxed_lock_counter)
+            .unwrap_err();
+        assert_eq!(replayed.message(), failure.message());
+        assert_eq!(replayed.schedule(), failure.schedule());
+        assert!(!replayed.trace().is_empty());
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::epoch;
+
+/// An `Arc<T>` that can be replaced while other threads read it, like the
+/// `arc-swap` crate.
+///
+/// A reader gets its own `Arc` to the current value with `load` and keeps using
+/// that snapshot for as long as it likes. A writer publishes a whole new value
+/// with `store` or `rcu`; readers are never blocked by it, they just see the new
+/// value from their next `load` on. That suits data that is read all the time
+/// and replaced now and then, such as a configuration.
+///
+/// Internally it is a single pointer from `Arc::into_raw`. The catch is the gap
+/// between reading that pointer and incrementing the reference count: a writer
+/// could replace the value and drop the last reference in between. Readers close
+/// the gap by pinning themselves in `epoch` while they do both, and writers drop
+/// the reference to a replaced value only once no such reader is pinned.
+pub struct AtomicArc<T: Send + Sync + 'static> {
+    /// Owns one reference to the current value.
+    ptr: AtomicPtr<T>,
+}
+
+impl<T: Send + Sync + 'static> AtomicArc<T> {
+    /// Creates an `AtomicArc` that holds `value`.
+    pub fn new(value: Arc<T>) -> Self {
+        AtomicArc {
+            ptr: AtomicPtr::new(Arc::into_raw(value).cast_mut()),
+        }
+    }
+
+    /// Returns a snapshot of the current value.
+    ///
+    /// It never waits for a writer or for other readers: it only pins the thread
+    /// for the moment it takes to increment the reference count. Unlike other
+    /// pins, this one never frees garbage, so a reader never ends up running the
+    /// `Drop` of a value that a writer replaced.
+    pub fn load(&self) -> Arc<T> {
+        let _guard = epoch::pin_no_collect();
+        // `Acquire` pairs with the `AcqRel` of writers, so we see the value that
+        // was written before its pointer was published.
+        let ptr = self.ptr.load(Ordering::Acquire);
+        // Safety: The pointer comes from `Arc::into_raw`, and the reference owned
+        // by `self` is only dropped after every thread pinned now has unpinned.
+        unsafe {
+            Arc::increment_strong_count(ptr);
+            Arc::from_raw(ptr)
+        }
+    }
+
+    /// Replaces the value.
+    pub fn store(&self, value: Arc<T>) {
+        drop(self.swap(value));
+    }
+
+    /// Replaces the value and returns the old one.
+    pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+        let guard = epoch::pin();
+        let old = self
+            .ptr
+            .swap(Arc::into_raw(value).cast_mut(), Ordering::AcqRel);
+        // Safety: `old` came from `Arc::into_raw`, and we took over the reference
+        // that `self` owned to it.
+        let old = unsafe { Arc::from_raw(old) };
+        Self::retire(&guard, old)
+    }
+
+    /// Replaces the value with `new` if it is still `current`, compared by
+    /// pointer. Returns the value that was actually there if not.
+    pub fn compare_and_swap(&self, current: &Arc<T>, new: Arc<T>) -> Result<Arc<T>, Arc<T>> {
+        let guard = epoch::pin();
+        let new = Arc::into_raw(new).cast_mut();
+        // `current` keeps its value alive, so its address cannot be reused by
+        // another value in the meantime.
+        match self.ptr.compare_exchange(
+            Arc::as_ptr(current).cast_mut(),
+            new,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            // Safety: As in `swap`.
+            Ok(old) => Ok(Self::retire(&guard, unsafe { Arc::from_raw(old) })),
+            Err(_) => {
+                // Safety: `new` was never published, so its reference is still ours.
+                drop(unsafe { Arc::from_raw(new) });
+                Err(self.load())
+            }
+        }
+    }
+
+    /// Replaces the value with `update(old)`, retrying with the newer value if
+    /// another thread replaced it in the meantime (read-copy-update). Returns
+    /// the value that was replaced.
+    ///
+    /// `update` may run several times, so it should not have side effects.
+    pub fn rcu<F>(&self, mut update: F) -> Arc<T>
+    where
+        F: FnMut(&T) -> T,
+    {
+        let mut current = self.load();
+        loop {
+            let new = Arc::new(update(&current));
+            match self.compare_and_swap(&current, new) {
+                Ok(old) => return old,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Hands out the reference to a replaced value that `self` owned.
+    ///
+    /// Readers that are pinned right now may still be about to increment its
+    /// reference count, so the caller gets a new reference, and the old one is
+    /// dropped once those readers are done.
+    fn retire(guard: &epoch::Guard, old: Arc<T>) -> Arc<T> {
+        let result = Arc::clone(&old);
+        guard.defer(move || drop(old));
+        result
+    }
+}
+
+impl<T: Default + Send + Sync + 'static> Default for AtomicArc<T> {
+    fn default() -> Self {
+        Self::new(Arc::default())
+    }
+}
+
+impl<T: Send + Sync + 'static> Drop for AtomicArc<T> {
+    fn drop(&mut self) {
+        // Safety: Loads borrow `self`, so none is running any more, and the
+        // reference that `self` owned is given up exactly once.
+        drop(unsafe { Arc::from_raw(*self.ptr.get_mut()) });
+    }
+}
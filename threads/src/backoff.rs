@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use crate::shim::{hint, thread};
+
+/// A strategy for what a thread does after it failed to take a lock.
+///
+/// A fresh value is created (via `Default`) for every acquisition attempt and
+/// `snooze` is called once per failed attempt, so a strategy can keep state such
+/// as "how often have I failed so far" without any synchronisation.
+pub trait Backoff: Default {
+    /// Waits a little before the next attempt to take the lock.
+    fn snooze(&mut self);
+}
+
+/// Calls `hint::spin_loop()` once per failed attempt.
+/// This is what `SpinLock` has always done, so it is the default strategy.
+#[derive(Debug, Default)]
+pub struct Spin;
+
+impl Backoff for Spin {
+    fn snooze(&mut self) {
+        hint::spin_loop();
+    }
+}
+
+/// Spins a fixed number of times (`SPINS`) per failed attempt.
+#[derive(Debug, Default)]
+pub struct Constant<const SPINS: u32 = 16>;
+
+impl<const SPINS: u32> Backoff for Constant<SPINS> {
+    fn snooze(&mut self) {
+        for _ in 0..SPINS {
+            hint::spin_loop();
+        }
+    }
+}
+
+/// Doubles the number of spins after every failed attempt, up to `CAP` spins.
+///
+/// Backing off exponentially spreads out the retries of the waiting threads, so
+/// fewer of them hit the lock's cache line at the same moment.
+#[derive(Debug)]
+pub struct Exponential<const CAP: u32 = 1024> {
+    spins: u32,
+}
+
+impl<const CAP: u32> Default for Exponential<CAP> {
+    fn default() -> Self {
+        Self { spins: 1 }
+    }
+}
+
+impl<const CAP: u32> Backoff for Exponential<CAP> {
+    fn snooze(&mut self) {
+        for _ in 0..self.spins {
+            hint::spin_loop();
+        }
+        self.spins = self.spins.saturating_mul(2).min(CAP);
+    }
+}
+
+/// Spins for the first `SPINS` failed attempts, then yields the CPU with
+/// `thread::yield_now()` on every further attempt.
+///
+/// Yielding lets the lock holder run when there are more threads than cores,
+/// which a pure spin strategy does not.
+#[derive(Debug, Default)]
+pub struct SpinThenYield<const SPINS: u32 = 100> {
+    attempts: u32,
+}
+
+impl<const SPINS: u32> Backoff for SpinThenYield<SPINS> {
+    fn snooze(&mut self) {
+        if self.attempts < SPINS {
+            self.attempts += 1;
+            hint::spin_loop();
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+/// Spins for the first `SPINS` failed attempts, then puts the thread to sleep
+/// with `thread::park_timeout` on every further attempt.
+///
+/// A plain spinlock keeps no list of waiters, so nobody will ever `unpark` us.
+/// The park therefore always runs into its (short) timeout, after which we try
+/// again. It still takes the waiter off the CPU completely, unlike yielding.
+#[derive(Debug, Default)]
+pub struct SpinThenPark<const SPINS: u32 = 100> {
+    attempts: u32,
+}
+
+impl<const SPINS: u32> Backoff for SpinThenPark<SPINS> {
+    fn snooze(&mut self) {
+        if self.attempts < SPINS {
+            self.attempts += 1;
+            hint::spin_loop();
+        } else {
+            thread::park_timeout(Duration::from_micros(50));
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::PoisonError;
+
+use crate::condvar::SpinCondvar;
+use crate::spinlock::SpinLock;
+
+/// Lets a fixed number of threads wait until all of them have reached the same
+/// point, for example to start them all at once.
+///
+/// The barrier can be used again and again. Every round has a generation
+/// number: a thread only waits for the generation it arrived in, so a thread
+/// that is quick to arrive at the next round cannot be mistaken for a late
+/// arrival of the current one.
+pub struct Barrier {
+    state: SpinLock<BarrierState>,
+    released: SpinCondvar,
+    parties: usize,
+}
+
+struct BarrierState {
+    /// How many threads have arrived in the current generation.
+    arrived: usize,
+    generation: usize,
+}
+
+/// Tells which of the threads released by `Barrier::wait` was the last to arrive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns `true` for exactly one thread per round: the one that arrived
+    /// last and released the others.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Creates a barrier for `parties` threads. Like `std::sync::Barrier`, a
+    /// barrier for 0 threads behaves like one for a single thread.
+    pub const fn new(parties: usize) -> Self {
+        Self {
+            state: SpinLock::new(BarrierState {
+                arrived: 0,
+                generation: 0,
+            }),
+            released: SpinCondvar::new(),
+            parties,
+        }
+    }
+
+    /// Blocks until all parties have called `wait` in this round.
+    pub fn wait(&self) -> BarrierWaitResult {
+        // Nothing can panic while the state is locked, so poisoning is harmless.
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let generation = state.generation;
+        state.arrived += 1;
+        if state.arrived >= self.parties {
+            // The last one in starts the next generation and wakes the others.
+            state.arrived = 0;
+            state.generation = generation.wrapping_add(1);
+            drop(state);
+            self.released.notify_all();
+            return BarrierWaitResult(true);
+        }
+        let _state = self
+            .released
+            .wait_while(state, |state| state.generation == generation)
+            .unwrap_or_else(PoisonError::into_inner);
+        BarrierWaitResult(false)
+    }
+}
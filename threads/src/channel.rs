@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use std::sync::mpsc::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+use std::sync::{Arc, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::condvar::SpinCondvar;
+use crate::spinlock::{SpinLock, SpinLockGuard};
+
+/// The state shared by all senders and receivers of one channel.
+struct Channel<T> {
+    state: SpinLock<State<T>>,
+    /// Signalled when an item is added, or when the last sender is gone.
+    not_empty: SpinCondvar,
+    /// Signalled when an item is removed, or when the last receiver is gone.
+    not_full: SpinCondvar,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receivers: usize,
+}
+
+/// The sending half of a channel created by `bounded`. It can be cloned to
+/// send from several threads.
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+/// The receiving half of a channel created by `bounded`. It can be cloned to
+/// receive on several threads; every item is received exactly once.
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+/// Creates a multi-producer, multi-consumer channel that holds at most
+/// `capacity` items. Sending to a full channel blocks until there is room.
+///
+/// When every `Sender` has been dropped, receivers still get the items that are
+/// left in the channel and then a `RecvError`, so there is no need for a "poison
+/// pill" item to tell them to stop. Likewise, sending fails once every
+/// `Receiver` is gone. The error types are the ones from `std::sync::mpsc`.
+///
+/// # Panics
+///
+/// Panics if `capacity` is 0.
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(
+        capacity > 0,
+        "a bounded channel needs a capacity of at least 1"
+    );
+    let channel = Arc::new(Channel {
+        state: SpinLock::new(State {
+            queue: VecDeque::with_capacity(capacity),
+            capacity,
+            senders: 1,
+            receivers: 1,
+        }),
+        not_empty: SpinCondvar::new(),
+        not_full: SpinCondvar::new(),
+    });
+    (
+        Sender {
+            channel: Arc::clone(&channel),
+        },
+        Receiver { channel },
+    )
+}
+
+impl<T> Channel<T> {
+    /// Locks the channel state. No operation on it can panic halfway, so a
+    /// poisoned lock (a panic elsewhere while holding it) is harmless.
+    fn state(&self) -> SpinLockGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T> Sender<T> {
+    /// Sends an item, blocking while the channel is full.
+    /// Fails, handing the item back, if all receivers have been dropped.
+    pub fn send(&self, item: T) -> Result<(), SendError<T>> {
+        let channel = &*self.channel;
+        let mut state = channel
+            .not_full
+            .wait_while(channel.state(), |state| {
+                state.receivers > 0 && state.queue.len() == state.capacity
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+        if state.receivers == 0 {
+            return Err(SendError(item));
+        }
+        state.queue.push_back(item);
+        drop(state);
+        channel.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Sends an item if there is room for it right now.
+    pub fn try_send(&self, item: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.channel.state();
+        if state.receivers == 0 {
+            return Err(TrySendError::Disconnected(item));
+        }
+        if state.queue.len() == state.capacity {
+            return Err(TrySendError::Full(item));
+        }
+        state.queue.push_back(item);
+        drop(state);
+        self.channel.not_empty.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives an item, blocking while the channel is empty.
+    /// Fails once the channel is empty and all senders have been dropped.
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let channel = &*self.channel;
+        let mut state = channel
+            .not_empty
+            .wait_while(channel.state(), |state| {
+                state.senders > 0 && state.queue.is_empty()
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+        let item = state.queue.pop_front().ok_or(RecvError)?;
+        drop(state);
+        channel.not_full.notify_one();
+        Ok(item)
+    }
+
+    /// Receives an item if one is available right now.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.channel.state();
+        match state.queue.pop_front() {
+            Some(item) => {
+                drop(state);
+                self.channel.not_full.notify_one();
+                Ok(item)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Receives an item, blocking for at most `timeout` while the channel is empty.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return self
+                .recv()
+                .map_err(|RecvError| RecvTimeoutError::Disconnected);
+        };
+
+        let channel = &*self.channel;
+        let mut state = channel.state();
+        loop {
+            if let Some(item) = state.queue.pop_front() {
+                drop(state);
+                channel.not_full.notify_one();
+                return Ok(item);
+            }
+            if state.senders == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = channel
+                .not_empty
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+
+    /// Returns an iterator that receives items until the channel is disconnected.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+}
+
+/// An iterator over the items of a channel, see `Receiver::iter`.
+pub struct Iter<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+    fn next(&mut self) -> Option<T> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.state().senders += 1;
+        Self {
+            channel: Arc::clone(&self.channel),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.channel.state().receivers += 1;
+        Self {
+            channel: Arc::clone(&self.channel),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.channel.state();
+        state.senders -= 1;
+        let last = state.senders == 0;
+        drop(state);
+        if last {
+            // Wake every blocked receiver so it can see the disconnection.
+            self.channel.not_empty.notify_all();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.channel.state();
+        state.receivers -= 1;
+        let last = state.receivers == 0;
+        drop(state);
+        if last {
+            // Wake every blocked sender so it can see the disconnection.
+            self.channel.not_full.notify_all();
+        }
+    }
+}
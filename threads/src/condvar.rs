@@ -0,0 +1,155 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, LockResult, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::backoff::{Backoff, SpinThenYield};
+use crate::spinlock::{SpinLock, SpinLockGuard};
+use crate::waiter::Waiter;
+
+/// A condition variable for use with `SpinLock`.
+///
+/// It lets a thread sleep until another thread signals that the data protected
+/// by a `SpinLock` has changed, for example that a queue is no longer empty.
+/// The API follows `std::sync::Condvar`: `wait` takes the guard, releases the
+/// lock while the thread sleeps and locks it again before returning.
+///
+/// Waiting threads are parked, not spinning, and are kept in a FIFO queue, so
+/// `notify_one` wakes the thread that has been waiting the longest. As with
+/// every condition variable, a woken thread must re-check its condition: use
+/// `wait_while`, or call `wait` in a loop.
+pub struct SpinCondvar {
+    waiters: SpinLock<VecDeque<Arc<Waiter>>, SpinThenYield>,
+}
+
+/// Tells whether a `wait_timeout` returned because the time ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    /// Returns `true` if the wait ended because the timeout elapsed.
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Default for SpinCondvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpinCondvar {
+    /// Creates a new condition variable with no waiting threads.
+    pub const fn new() -> Self {
+        Self {
+            waiters: SpinLock::with_backoff(VecDeque::new()),
+        }
+    }
+
+    /// Locks the wait queue. Pushing and popping cannot leave the queue in a
+    /// broken state, so there is nothing to worry about if it was poisoned.
+    fn waiters(&self) -> SpinLockGuard<'_, VecDeque<Arc<Waiter>>, SpinThenYield> {
+        self.waiters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Releases the lock, sleeps until notified and then re-acquires the lock.
+    ///
+    /// Returns a `PoisonError` if the lock was poisoned while we were asleep.
+    pub fn wait<'a, T, B: Backoff>(
+        &self,
+        guard: SpinLockGuard<'a, T, B>,
+    ) -> LockResult<SpinLockGuard<'a, T, B>> {
+        let lock = guard.lock;
+        let waiter = Waiter::new();
+        // We join the queue *before* releasing the lock. A notifying thread has to
+        // take the lock to change the data we are waiting for, so it cannot send
+        // its notification in between and leave us asleep.
+        self.waiters().push_back(Arc::clone(&waiter));
+        drop(guard);
+
+        waiter.wait();
+        lock.lock()
+    }
+
+    /// Waits as long as `condition` returns `true` for the protected data.
+    ///
+    /// This takes care of spurious wake-ups and of other threads that got to the
+    /// data first: when it returns, the condition is known to be `false`.
+    pub fn wait_while<'a, T, B, F>(
+        &self,
+        mut guard: SpinLockGuard<'a, T, B>,
+        mut condition: F,
+    ) -> LockResult<SpinLockGuard<'a, T, B>>
+    where
+        B: Backoff,
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Like `wait`, but gives up after `timeout`.
+    ///
+    /// The lock is re-acquired either way. The returned `WaitTimeoutResult`
+    /// tells whether the wait timed out.
+    pub fn wait_timeout<'a, T, B: Backoff>(
+        &self,
+        guard: SpinLockGuard<'a, T, B>,
+        timeout: Duration,
+    ) -> LockResult<(SpinLockGuard<'a, T, B>, WaitTimeoutResult)> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            // Too far in the future to represent: wait without a timeout.
+            return match self.wait(guard) {
+                Ok(guard) => Ok((guard, WaitTimeoutResult(false))),
+                Err(poisoned) => Err(PoisonError::new((
+                    poisoned.into_inner(),
+                    WaitTimeoutResult(false),
+                ))),
+            };
+        };
+
+        let lock = guard.lock;
+        let waiter = Waiter::new();
+        self.waiters().push_back(Arc::clone(&waiter));
+        drop(guard);
+
+        let mut timed_out = !waiter.wait_until(deadline);
+        if timed_out {
+            // Leave the queue so that no notification is wasted on us. If we are
+            // no longer in it, a `notify_one` picked us just as the time ran out:
+            // we count that as a wake-up, otherwise the notification would be lost.
+            let mut waiters = self.waiters();
+            match waiters.iter().position(|w| Arc::ptr_eq(w, &waiter)) {
+                Some(index) => {
+                    waiters.remove(index);
+                }
+                None => timed_out = false,
+            }
+        }
+
+        let result = WaitTimeoutResult(timed_out);
+        match lock.lock() {
+            Ok(guard) => Ok((guard, result)),
+            Err(poisoned) => Err(PoisonError::new((poisoned.into_inner(), result))),
+        }
+    }
+
+    /// Wakes up the thread that has been waiting the longest, if any.
+    pub fn notify_one(&self) {
+        let waiter = self.waiters().pop_front();
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+    }
+
+    /// Wakes up all waiting threads.
+    pub fn notify_all(&self) {
+        // Take the whole queue first so that we don't hold its lock while waking.
+        let waiters = std::mem::take(&mut *self.waiters());
+        for waiter in waiters {
+            waiter.wake();
+        }
+    }
+}
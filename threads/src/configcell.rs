@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::atomicarc::AtomicArc;
+
+/// A configuration that can be reloaded while threads keep reading it.
+///
+/// Readers take snapshots with `load`, which never waits, even while a new
+/// version is published. Threads that want to react to a reload, say to resize
+/// a pool, `subscribe` and receive every new version over a channel.
+///
+/// Writers take turns, so subscribers receive the versions in the order in
+/// which they were published.
+pub struct ConfigCell<T: Send + Sync + 'static> {
+    value: AtomicArc<T>,
+    /// Also held while publishing, to keep writers in order.
+    subscribers: Mutex<Vec<Sender<Arc<T>>>>,
+}
+
+impl<T: Send + Sync + 'static> ConfigCell<T> {
+    /// Creates a cell holding `value`.
+    pub fn new(value: T) -> Self {
+        ConfigCell {
+            value: AtomicArc::new(Arc::new(value)),
+            subscribers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Returns a snapshot of the current version.
+    pub fn load(&self) -> Arc<T> {
+        self.value.load()
+    }
+
+    /// Publishes a new version.
+    pub fn store(&self, value: T) {
+        self.rcu(|_| value);
+    }
+
+    /// Publishes the version that `update` makes from the current one, and
+    /// returns it. No other version is published in between.
+    pub fn rcu<F>(&self, update: F) -> Arc<T>
+    where
+        F: FnOnce(&T) -> T,
+    {
+        // If `update` panics, nothing has been published yet, so the lock can
+        // be used as is even if it has been poisoned by that.
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let new = Arc::new(update(&self.value.load()));
+        self.value.store(Arc::clone(&new));
+        // Forget subscribers that dropped their receiver.
+        subscribers.retain(|subscriber| subscriber.send(Arc::clone(&new)).is_ok());
+        new
+    }
+
+    /// Returns a receiver for every version published from now on.
+    pub fn subscribe(&self) -> Receiver<Arc<T>> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+}
+
+/// Polls a configuration file and publishes a new version to a `ConfigCell`
+/// whenever its contents change. Dropping it stops the polling.
+pub struct FileWatcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FileWatcher {
+    /// Starts a thread that reads the file at `path` every `interval`.
+    ///
+    /// When the contents differ from the last read, `parse` turns them into a
+    /// new version. If it returns `None`, for example because the file was read
+    /// while only half written, the current version stays until the file
+    /// changes again. A missing file is treated the same way.
+    pub fn new<T, P>(
+        cell: Arc<ConfigCell<T>>,
+        path: impl Into<PathBuf>,
+        interval: Duration,
+        parse: P,
+    ) -> FileWatcher
+    where
+        T: Send + Sync + 'static,
+        P: Fn(&str) -> Option<T> + Send + 'static,
+    {
+        let path = path.into();
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new()
+            .name(format!("watch {}", path.display()))
+            .spawn({
+                let stop = Arc::clone(&stop);
+                move || {
+                    let mut last = None;
+                    while !stop.load(Ordering::Relaxed) {
+                        if let Ok(contents) = fs::read_to_string(&path)
+                            && last.as_ref() != Some(&contents)
+                        {
+                            if let Some(value) = parse(&contents) {
+                                cell.store(value);
+                            }
+                            last = Some(contents);
+                        }
+                        // `drop` unparks us, so stopping does not wait for the interval.
+                        thread::park_timeout(interval);
+                    }
+                }
+            })
+            .expect("failed to spawn the file watcher thread");
+
+        FileWatcher {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
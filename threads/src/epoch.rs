@@ -0,0 +1,375 @@
+use std::cell::{Cell, RefCell};
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering, fence};
+use std::sync::{Mutex, PoisonError};
+
+/// Every this many pins, and whenever a bag holds this many items, a thread
+/// tries to advance the global epoch and frees the garbage that has become safe.
+const COLLECT_EVERY: usize = 64;
+
+/// The global epoch. It only moves from `e` to `e + 1` once every pinned
+/// thread has been pinned in epoch `e`.
+static EPOCH: AtomicUsize = AtomicUsize::new(0);
+
+/// The head of the global, append-only list of participants.
+static PARTICIPANTS: AtomicPtr<Participant> = AtomicPtr::new(ptr::null_mut());
+
+/// Garbage left behind by threads that exited before it could be freed.
+/// The next collection on any thread takes it over.
+static ORPHANS: Mutex<Vec<Deferred>> = Mutex::new(Vec::new());
+
+/// The entry of one thread in the global list.
+///
+/// Like the slots of `hazard`, participants are never freed. A thread claims a
+/// free one the first time it pins, and gives it back when it exits.
+struct Participant {
+    /// `(epoch << 1) | 1` while the thread is pinned, 0 while it is not.
+    state: AtomicUsize,
+    /// `true` while a thread owns this participant.
+    in_use: AtomicBool,
+    next: *const Participant,
+}
+
+/// A function to run once no pinned thread can still see the garbage it frees.
+struct Deferred {
+    /// The global epoch when the function was deferred.
+    epoch: usize,
+    call: Box<dyn FnOnce() + Send>,
+}
+
+/// The epoch state of the current thread.
+struct Local {
+    participant: &'static Participant,
+    /// How many `Guard`s of this thread are alive. Pinning is reentrant.
+    guards: Cell<usize>,
+    /// Counts pins, to collect every `COLLECT_EVERY` of them.
+    pins: Cell<usize>,
+    /// The garbage deferred by this thread that has not been freed yet.
+    bag: RefCell<Vec<Deferred>>,
+}
+
+thread_local! {
+    static LOCAL: Local = Local::register();
+}
+
+/// A guard that keeps the current thread pinned, see `pin`.
+///
+/// It cannot be sent to another thread, because pinning is a property of the
+/// thread, not of the guard.
+pub struct Guard {
+    marker: PhantomData<*const ()>,
+}
+
+/// Pins the current thread, so that nothing that is reachable right now gets
+/// freed until the returned guard is dropped.
+///
+/// Epoch-based reclamation is the other common way, besides the hazard pointers
+/// in `hazard`, to free nodes of a lock-free data structure that other threads
+/// may still be reading. Instead of announcing every single pointer it uses, a
+/// reader only announces that it is active, by pinning itself to the current
+/// global epoch. A node that has been unlinked is handed to `Guard::defer_destroy`
+/// and tagged with the epoch of that moment. The epoch cannot advance twice
+/// while any thread stays pinned, and a thread that pins later cannot reach the
+/// node any more, so once the global epoch is two ahead of the tag, nobody can
+/// hold a pointer to the node and it is freed.
+///
+/// This makes reads cheaper than with hazard pointers, but a single thread that
+/// stays pinned for a long time holds back all garbage, not just a few nodes.
+pub fn pin() -> Guard {
+    pin_inner(true)
+}
+
+/// Like `pin`, but never collects garbage, so it takes a bounded number of
+/// steps and never runs deferred functions, whose `Drop` impls could do
+/// anything. For readers that must not be made to do the writers' cleanup.
+pub(crate) fn pin_no_collect() -> Guard {
+    pin_inner(false)
+}
+
+fn pin_inner(collect: bool) -> Guard {
+    LOCAL.with(|local| {
+        let guards = local.guards.get();
+        local.guards.set(guards + 1);
+        if guards == 0 {
+            let epoch = EPOCH.load(Ordering::Relaxed);
+            local
+                .participant
+                .state
+                .store((epoch << 1) | 1, Ordering::Relaxed);
+            // Pairs with the fence in `try_advance`: either the advancing thread
+            // sees that we are pinned, or we see everything that was unlinked
+            // before the epoch moved on.
+            fence(Ordering::SeqCst);
+            if !collect {
+                return;
+            }
+
+            let pins = local.pins.get().wrapping_add(1);
+            local.pins.set(pins);
+            if pins % COLLECT_EVERY == 0 {
+                local.collect();
+            }
+        }
+    });
+    Guard {
+        marker: PhantomData,
+    }
+}
+
+/// Returns `true` if the current thread is pinned.
+pub fn is_pinned() -> bool {
+    LOCAL.with(|local| local.guards.get() > 0)
+}
+
+/// Tries to advance the global epoch and frees the garbage of the current
+/// thread (and of threads that have exited) that has become safe to free.
+///
+/// This happens on its own every now and then. Call it directly to release
+/// memory right away, for example after a burst of work. While the current
+/// thread is pinned itself, garbage deferred in the current epoch stays.
+pub fn flush() {
+    LOCAL.with(Local::collect);
+}
+
+impl Guard {
+    /// Runs `f` once no thread that is pinned right now is pinned any more.
+    pub fn defer<F: FnOnce() + Send + 'static>(&self, f: F) {
+        // Pairs with the fence in `pin`: a thread that pins in a later epoch
+        // sees everything we unlinked before deferring.
+        fence(Ordering::SeqCst);
+        let deferred = Deferred {
+            epoch: EPOCH.load(Ordering::Relaxed),
+            call: Box::new(f),
+        };
+        LOCAL.with(|local| {
+            let mut bag = local.bag.borrow_mut();
+            bag.push(deferred);
+            let full = bag.len() >= COLLECT_EVERY;
+            drop(bag);
+            if full {
+                local.collect();
+            }
+        });
+    }
+
+    /// Frees a node that has been unlinked from its data structure, once no
+    /// thread that may have seen it is pinned any more.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `Box::into_raw`, must no longer be reachable by
+    /// threads that pin from now on, and must not be destroyed twice. Since the
+    /// node may be freed on another thread, `T` must be `Send`.
+    pub unsafe fn defer_destroy<T: Send + 'static>(&self, ptr: *mut T) {
+        let node = SendPtr(ptr);
+        // Safety: Guaranteed by the caller.
+        self.defer(move || unsafe { node.free() });
+    }
+}
+
+/// Unpins the current thread when its last guard is dropped.
+impl Drop for Guard {
+    fn drop(&mut self) {
+        // Without `try_with`, dropping a guard while this thread's locals are
+        // torn down would panic. The participant is released then anyway.
+        let _ = LOCAL.try_with(|local| {
+            let guards = local.guards.get() - 1;
+            local.guards.set(guards);
+            if guards == 0 {
+                // `Release`: our reads of shared nodes happen before the node
+                // can be freed by a thread that sees us unpinned.
+                local.participant.state.store(0, Ordering::Release);
+            }
+        });
+    }
+}
+
+/// A raw pointer that `defer_destroy` moves to the thread that frees it.
+struct SendPtr<T>(*mut T);
+
+// Safety: `defer_destroy` only accepts pointers to `Send` types.
+unsafe impl<T: Send> Send for SendPtr<T> {}
+
+impl<T> SendPtr<T> {
+    /// Takes `self` as a whole, so that closures capture the `Send` wrapper and
+    /// not just the raw pointer inside of it.
+    unsafe fn free(self) {
+        // Safety: `defer_destroy` requires the pointer to come from `Box::into_raw`.
+        drop(unsafe { Box::from_raw(self.0) });
+    }
+}
+
+impl Local {
+    /// Claims a free participant, or adds a new one to the list.
+    fn register() -> Self {
+        Self {
+            participant: claim_participant(),
+            guards: Cell::new(0),
+            pins: Cell::new(0),
+            bag: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Tries to advance the global epoch, then frees the garbage in our bag that
+    /// is at least two epochs old.
+    fn collect(&self) {
+        let epoch = try_advance();
+        let mut bag = self.bag.borrow_mut();
+        if let Ok(mut orphans) = ORPHANS.try_lock() {
+            bag.append(&mut orphans);
+        }
+        let (ready, pending): (Vec<_>, Vec<_>) = bag
+            .drain(..)
+            .partition(|deferred| epoch.wrapping_sub(deferred.epoch) >= 2);
+        *bag = pending;
+        // The deferred functions may defer more garbage, so release the bag first.
+        drop(bag);
+        for deferred in ready {
+            (deferred.call)();
+        }
+    }
+}
+
+/// Hands the remaining garbage over to `ORPHANS` and releases the participant.
+impl Drop for Local {
+    fn drop(&mut self) {
+        self.participant.state.store(0, Ordering::Release);
+        let bag = self.bag.get_mut();
+        if !bag.is_empty() {
+            ORPHANS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .append(bag);
+        }
+        self.participant.in_use.store(false, Ordering::Release);
+    }
+}
+
+fn claim_participant() -> &'static Participant {
+    let mut current = PARTICIPANTS.load(Ordering::Acquire);
+    while !current.is_null() {
+        // Safety: Participants are never freed.
+        let participant = unsafe { &*current };
+        if !participant.in_use.load(Ordering::Relaxed)
+            && participant
+                .in_use
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        {
+            return participant;
+        }
+        current = participant.next.cast_mut();
+    }
+
+    let participant: &'static mut Participant = Box::leak(Box::new(Participant {
+        state: AtomicUsize::new(0),
+        in_use: AtomicBool::new(true),
+        next: ptr::null(),
+    }));
+    let mut head = PARTICIPANTS.load(Ordering::Relaxed);
+    loop {
+        participant.next = head;
+        match PARTICIPANTS.compare_exchange_weak(
+            head,
+            participant,
+            Ordering::Release,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return participant,
+            Err(actual) => head = actual,
+        }
+    }
+}
+
+/// Moves the global epoch forward by one, unless a thread is still pinned in an
+/// older epoch. Returns the global epoch afterwards.
+fn try_advance() -> usize {
+    let epoch = EPOCH.load(Ordering::Relaxed);
+    // Pairs with the fence in `pin`.
+    fence(Ordering::SeqCst);
+
+    let mut current = PARTICIPANTS.load(Ordering::Acquire);
+    while !current.is_null() {
+        // Safety: Participants are never freed.
+        let participant = unsafe { &*current };
+        let state = participant.state.load(Ordering::Relaxed);
+        if state & 1 == 1 && state >> 1 != epoch {
+            return epoch;
+        }
+        current = participant.next.cast_mut();
+    }
+
+    // `Release`: the threads that see the new epoch see everything that was
+    // unlinked before it. Losing the race is fine, the epoch moved on either way.
+    match EPOCH.compare_exchange(epoch, epoch + 1, Ordering::Release, Ordering::Relaxed) {
+        Ok(_) => epoch + 1,
+        Err(current) => current,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Counts its own drops, to see when the collector frees it.
+    struct DropCounter(Arc<AtomicUsize>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn pinned_reader_blocks_reclamation() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let (pinned_sender, pinned) = mpsc::channel();
+        let (unpin_sender, unpin) = mpsc::channel::<()>();
+        let reader = thread::spawn(move || {
+            let _guard = pin();
+            pinned_sender.send(()).unwrap();
+            unpin.recv().unwrap();
+        });
+
+        pinned.recv().unwrap();
+        let old = Box::into_raw(Box::new(DropCounter(Arc::clone(&drops))));
+        // Safety: `old` was never shared and was allocated with `Box`.
+        unsafe { pin().defer_destroy(old) };
+        for _ in 0..10 {
+            flush();
+        }
+        assert_eq!(
+            drops.load(Ordering::Relaxed),
+            0,
+            "freed under a pinned reader"
+        );
+
+        unpin_sender.send(()).unwrap();
+        reader.join().unwrap();
+        // With the reader gone, two epoch advances free the node. Other tests
+        // may keep the epoch back for a moment, so try a few more times.
+        for _ in 0..1000 {
+            if drops.load(Ordering::Relaxed) == 1 {
+                break;
+            }
+            flush();
+            thread::yield_now();
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn pinning_is_reentrant() {
+        assert!(!is_pinned());
+        let outer = pin();
+        let inner = pin();
+        drop(outer);
+        assert!(is_pinned());
+        drop(inner);
+        assert!(!is_pinned());
+    }
+}
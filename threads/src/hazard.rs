@@ -0,0 +1,220 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering, fence};
+use std::sync::{Mutex, PoisonError};
+
+/// Once a thread has retired this many nodes, it scans the hazard pointers
+/// and frees the nodes that nobody protects.
+const SCAN_THRESHOLD: usize = 64;
+
+/// One slot in the global list of hazard pointers.
+///
+/// Slots are never freed. A `HazardPointer` claims a free slot and gives it
+/// back when it is dropped, so the list only grows to the largest number of
+/// hazard pointers that were ever in use at the same time.
+struct Slot {
+    /// The pointer that must not be freed, or null.
+    protected: AtomicPtr<()>,
+    /// `true` while a `HazardPointer` owns this slot.
+    in_use: AtomicBool,
+    next: *const Slot,
+}
+
+/// The head of the global, append-only list of slots.
+static SLOTS: AtomicPtr<Slot> = AtomicPtr::new(ptr::null_mut());
+
+/// Nodes retired by threads that exited while some of them were still protected.
+/// The next scan on any thread takes them over.
+static ORPHANS: Mutex<Vec<Retired>> = Mutex::new(Vec::new());
+
+/// A node that has been unlinked from its data structure and waits to be freed.
+struct Retired {
+    ptr: *mut (),
+    free: unsafe fn(*mut ()),
+}
+
+// Safety: `retire` only accepts nodes that may be freed on any thread.
+unsafe impl Send for Retired {}
+
+/// The nodes this thread has retired. Whatever is left when the thread exits is
+/// handed over to `ORPHANS`, so nothing is leaked.
+struct RetiredList(Vec<Retired>);
+
+impl Drop for RetiredList {
+    fn drop(&mut self) {
+        scan(&mut self.0);
+        if !self.0.is_empty() {
+            ORPHANS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .append(&mut self.0);
+        }
+    }
+}
+
+thread_local! {
+    static RETIRED: RefCell<RetiredList> = const { RefCell::new(RetiredList(Vec::new())) };
+}
+
+/// A hazard pointer: announces to all threads that the node it protects must
+/// not be freed yet.
+///
+/// A lock-free data structure cannot simply free a node after unlinking it,
+/// because another thread may have loaded a pointer to it just before and is
+/// about to read it. With hazard pointers, a reader first publishes the pointer
+/// it is going to use (`protect`). A node that has been unlinked is `retire`d
+/// instead of freed, and is only freed once no hazard pointer refers to it.
+pub struct HazardPointer {
+    slot: &'static Slot,
+}
+
+impl HazardPointer {
+    /// Claims a hazard pointer, reusing a free slot if there is one.
+    pub fn new() -> Self {
+        let mut current = SLOTS.load(Ordering::Acquire);
+        while !current.is_null() {
+            // Safety: Slots are never freed.
+            let slot = unsafe { &*current };
+            if !slot.in_use.load(Ordering::Relaxed)
+                && slot
+                    .in_use
+                    .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                return Self { slot };
+            }
+            current = slot.next.cast_mut();
+        }
+
+        // Every slot is taken: add a new one to the front of the list.
+        let slot: &'static mut Slot = Box::leak(Box::new(Slot {
+            protected: AtomicPtr::new(ptr::null_mut()),
+            in_use: AtomicBool::new(true),
+            next: ptr::null(),
+        }));
+        let mut head = SLOTS.load(Ordering::Relaxed);
+        loop {
+            slot.next = head;
+            match SLOTS.compare_exchange_weak(head, slot, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => return Self { slot },
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    /// Loads the pointer stored in `src` and protects it.
+    ///
+    /// The returned pointer will not be freed until this hazard pointer is reset,
+    /// protects something else or is dropped, as long as the node was still
+    /// reachable through `src` when it was loaded. That is what the retry loop
+    /// makes sure of: a node is only retired after it has been unlinked.
+    pub fn protect<T>(&self, src: &AtomicPtr<T>) -> *mut T {
+        let mut ptr = src.load(Ordering::Relaxed);
+        loop {
+            self.slot.protected.store(ptr.cast(), Ordering::Relaxed);
+            // Pairs with the fence in `scan`: either the scanning thread sees our
+            // hazard pointer, or we see that the node has been unlinked.
+            fence(Ordering::SeqCst);
+            let current = src.load(Ordering::Acquire);
+            if current == ptr {
+                return ptr;
+            }
+            ptr = current;
+        }
+    }
+
+    /// Stops protecting the current node.
+    pub fn reset(&self) {
+        self.slot
+            .protected
+            .store(ptr::null_mut(), Ordering::Release);
+    }
+}
+
+impl Default for HazardPointer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Gives the slot back for other hazard pointers to use.
+impl Drop for HazardPointer {
+    fn drop(&mut self) {
+        self.reset();
+        self.slot.in_use.store(false, Ordering::Release);
+    }
+}
+
+/// Frees a node that has been unlinked from its data structure, as soon as no
+/// hazard pointer protects it any more.
+///
+/// # Safety
+///
+/// `ptr` must come from `Box::into_raw`, must no longer be reachable by threads
+/// that have not protected it yet, and must not be retired twice. Since the node
+/// may be freed on another thread, `T` must be `Send`.
+pub unsafe fn retire<T: Send>(ptr: *mut T) {
+    unsafe fn free<T>(ptr: *mut ()) {
+        // Safety: `retire` requires the pointer to come from `Box::into_raw`.
+        drop(unsafe { Box::from_raw(ptr.cast::<T>()) });
+    }
+    let mut retired = Some(Retired {
+        ptr: ptr.cast(),
+        free: free::<T>,
+    });
+
+    let _ = RETIRED.try_with(|list| {
+        let list = &mut list.borrow_mut().0;
+        list.extend(retired.take());
+        if list.len() >= SCAN_THRESHOLD {
+            scan(list);
+        }
+    });
+    if let Some(retired) = retired {
+        // This thread is exiting and its own list is gone already.
+        ORPHANS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(retired);
+    }
+}
+
+/// Frees every node retired by the current thread (or by threads that have
+/// exited) that is not protected by a hazard pointer.
+///
+/// This happens on its own every few retirements. Call it directly to release
+/// memory right away, for example after a burst of work.
+pub fn reclaim() {
+    let _ = RETIRED.try_with(|list| scan(&mut list.borrow_mut().0));
+}
+
+/// Frees the nodes in `retired` that no hazard pointer protects.
+fn scan(retired: &mut Vec<Retired>) {
+    if let Ok(mut orphans) = ORPHANS.try_lock() {
+        retired.append(&mut orphans);
+    }
+
+    // Pairs with the fence in `HazardPointer::protect`.
+    fence(Ordering::SeqCst);
+    let mut protected = HashSet::new();
+    let mut current = SLOTS.load(Ordering::Acquire);
+    while !current.is_null() {
+        // Safety: Slots are never freed.
+        let slot = unsafe { &*current };
+        let ptr = slot.protected.load(Ordering::Acquire);
+        if !ptr.is_null() {
+            protected.insert(ptr);
+        }
+        current = slot.next.cast_mut();
+    }
+
+    retired.retain(|node| {
+        if protected.contains(&node.ptr) {
+            return true;
+        }
+        // Safety: The node is unreachable and nobody protects it.
+        unsafe { (node.free)(node.ptr) };
+        false
+    });
+}
@@ -0,0 +1,81 @@
+use std::sync::PoisonError;
+use std::time::{Duration, Instant};
+
+use crate::condvar::SpinCondvar;
+use crate::spinlock::{SpinLock, SpinLockGuard};
+
+/// Lets threads wait until a counter has been counted down to zero, for
+/// example until N workers have finished starting up.
+///
+/// Unlike a `Barrier`, the threads that count down do not wait, and the latch
+/// cannot be reset: once it reaches zero it stays open.
+pub struct CountDownLatch {
+    count: SpinLock<usize>,
+    zero: SpinCondvar,
+}
+
+impl CountDownLatch {
+    /// Creates a latch that opens after `count` calls to `count_down`.
+    pub const fn new(count: usize) -> Self {
+        Self {
+            count: SpinLock::new(count),
+            zero: SpinCondvar::new(),
+        }
+    }
+
+    /// Locks the counter. Nothing can panic while it is locked, so poisoning is harmless.
+    fn lock(&self) -> SpinLockGuard<'_, usize> {
+        self.count.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Decrements the counter, and wakes all waiting threads when it reaches
+    /// zero. Does nothing if the latch is already open.
+    pub fn count_down(&self) {
+        let mut count = self.lock();
+        if *count == 0 {
+            return;
+        }
+        *count -= 1;
+        let open = *count == 0;
+        drop(count);
+        if open {
+            self.zero.notify_all();
+        }
+    }
+
+    /// Returns how many more `count_down` calls it takes to open the latch.
+    pub fn count(&self) -> usize {
+        *self.lock()
+    }
+
+    /// Blocks until the counter reaches zero.
+    pub fn wait(&self) {
+        let _count = self
+            .zero
+            .wait_while(self.lock(), |count| *count > 0)
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+
+    /// Blocks until the counter reaches zero or `timeout` has passed.
+    /// Returns `true` if the latch is open.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        // A timeout too large to represent as an `Instant` is as good as no timeout.
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            self.wait();
+            return true;
+        };
+        let mut count = self.lock();
+        while *count > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            count = self
+                .zero
+                .wait_timeout(count, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        true
+    }
+}
@@ -0,0 +1,31 @@
+// Threads library root
+pub mod atomicarc;
+pub mod backoff;
+pub mod barrier;
+pub mod channel;
+pub mod condvar;
+pub mod configcell;
+pub mod epoch;
+pub mod hazard;
+pub mod latch;
+pub mod lock;
+pub mod lockdep;
+pub mod mcslock;
+#[cfg(feature = "model")]
+pub mod model;
+pub mod msqueue;
+pub mod parkinglock;
+pub mod reentrantlock;
+pub mod rwspinlock;
+pub mod semaphore;
+pub mod seqlock;
+pub mod shardedmap;
+mod shim;
+pub mod spinlock;
+#[cfg(feature = "stats")]
+pub mod stats;
+pub mod sync;
+mod threadid;
+pub mod ticketlock;
+pub mod treiberstack;
+mod waiter;
@@ -0,0 +1,28 @@
+use std::ops::DerefMut;
+use std::sync::{LockResult, TryLockResult};
+
+/// The guard API shared by the locks in this crate.
+///
+/// Every lock hands out a guard that dereferences to the protected data and
+/// releases the lock when dropped, just like `SpinLockGuard`. Writing code
+/// against this trait instead of a concrete lock type lets the same benchmark
+/// or experiment run unchanged against every lock variant.
+///
+/// The results follow `std::sync::Mutex`, so code written against this trait
+/// reads the same as code using a `Mutex`. Locks that do not track poisoning
+/// never return a `PoisonError`.
+pub trait Lock<T> {
+    /// The RAII guard returned by `lock` and `try_lock`.
+    type Guard<'a>: DerefMut<Target = T>
+    where
+        Self: 'a;
+
+    /// Creates a new, unlocked lock protecting the given data.
+    fn new(value: T) -> Self;
+
+    /// Acquires the lock, waiting until it is available.
+    fn lock(&self) -> LockResult<Self::Guard<'_>>;
+
+    /// Attempts to acquire the lock without waiting.
+    fn try_lock(&self) -> TryLockResult<Self::Guard<'_>>;
+}
@@ -0,0 +1,503 @@
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+use std::ops::{Deref, DerefMut};
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{
+    LockResult, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    TryLockError, TryLockResult,
+};
+use std::thread;
+
+use crate::lock::Lock;
+
+/// The next number to hand out to a lock. Zero means "no number yet".
+#[cfg(debug_assertions)]
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Every order in which two locks have been taken so far, by any thread.
+static GRAPH: Mutex<Graph> = Mutex::new(Graph::new());
+
+thread_local! {
+    /// The locks the current thread holds, in the order it took them.
+    static HELD: RefCell<Vec<Held>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Implemented by every lock that lockdep watches.
+///
+/// A lock embeds a `LockId` and reports to lockdep around every acquisition:
+/// `will_lock` before it starts waiting, `locked` once it holds the lock, and
+/// `unlocked` when its guard is dropped. Attempts that cannot wait forever, like
+/// `try_lock`, only call `locked`: they cannot deadlock, but the locks taken
+/// while holding them can.
+pub trait Tracked {
+    /// The lock's node in the lock-order graph.
+    fn lock_id(&self) -> &LockId;
+
+    /// The kind of lock, such as `"SpinLock"`, for reports.
+    fn kind(&self) -> &'static str;
+}
+
+/// Identifies a lock in the lock-order graph, see `Tracked`.
+///
+/// Locks get a number when they are first taken rather than being known by
+/// their address: a lock may move while nobody holds it, and a new lock may end
+/// up at the address of a dropped one. In release builds it takes up no space.
+pub struct LockId {
+    #[cfg(debug_assertions)]
+    id: AtomicU64,
+}
+
+/// A lock in the current thread's list of held locks.
+#[derive(Clone, Copy)]
+struct Held {
+    id: u64,
+    kind: &'static str,
+}
+
+/// Records that some thread took `to` while holding `from`.
+struct Edge {
+    from: Held,
+    to: Held,
+    thread: String,
+    /// Where `to` was taken. Captured only the first time, so the cost of a
+    /// backtrace is only paid for lock orders we have not seen before.
+    backtrace: Backtrace,
+}
+
+/// The lock-order graph: an edge from `a` to `b` means that some thread has
+/// taken `b` while holding `a`. A cycle means that the threads taking part in
+/// it can end up waiting for each other forever.
+struct Graph {
+    edges: BTreeMap<u64, BTreeMap<u64, Edge>>,
+    /// The reverse edges, so that a dropped lock can be removed quickly.
+    sources: BTreeMap<u64, BTreeSet<u64>>,
+}
+
+impl LockId {
+    /// Creates an id without a number; one is drawn when the lock is first taken.
+    pub const fn new() -> Self {
+        LockId {
+            #[cfg(debug_assertions)]
+            id: AtomicU64::new(0),
+        }
+    }
+
+    #[cfg(debug_assertions)]
+    fn get(&self) -> u64 {
+        let id = self.id.load(Ordering::Relaxed);
+        if id != 0 {
+            return id;
+        }
+        let new = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        // Two threads may draw a number for the same lock; the first one wins.
+        match self
+            .id
+            .compare_exchange(0, new, Ordering::Relaxed, Ordering::Relaxed)
+        {
+            Ok(_) => new,
+            Err(id) => id,
+        }
+    }
+}
+
+impl Default for LockId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Forgets the lock orders of a dropped lock; its number is never reused.
+impl Drop for LockId {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        {
+            let id = *self.id.get_mut();
+            if id != 0 {
+                graph().forget(id);
+            }
+        }
+    }
+}
+
+impl Held {
+    fn of<L: Tracked + ?Sized>(lock: &L) -> Self {
+        Held {
+            #[cfg(debug_assertions)]
+            id: lock.lock_id().get(),
+            #[cfg(not(debug_assertions))]
+            id: 0,
+            kind: lock.kind(),
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("{} #{}", self.kind, self.id)
+    }
+}
+
+impl Graph {
+    const fn new() -> Self {
+        Graph {
+            edges: BTreeMap::new(),
+            sources: BTreeMap::new(),
+        }
+    }
+
+    fn contains(&self, from: u64, to: u64) -> bool {
+        self.edges
+            .get(&from)
+            .is_some_and(|edges| edges.contains_key(&to))
+    }
+
+    fn insert(&mut self, edge: Edge) {
+        let (from, to) = (edge.from.id, edge.to.id);
+        self.edges.entry(from).or_default().insert(to, edge);
+        self.sources.entry(to).or_default().insert(from);
+    }
+
+    #[cfg(debug_assertions)]
+    fn forget(&mut self, id: u64) {
+        for to in self
+            .edges
+            .remove(&id)
+            .into_iter()
+            .flat_map(|edges| edges.into_keys())
+        {
+            if let Some(sources) = self.sources.get_mut(&to) {
+                sources.remove(&id);
+            }
+        }
+        for from in self.sources.remove(&id).into_iter().flatten() {
+            if let Some(edges) = self.edges.get_mut(&from) {
+                edges.remove(&id);
+            }
+        }
+    }
+
+    /// Returns the edges of a shortest path from `from` to `to`, if there is one.
+    fn path(&self, from: u64, to: u64) -> Option<Vec<&Edge>> {
+        // Breadth-first search, remembering the edge through which we reached
+        // every node so that the path can be walked back from `to`.
+        let mut reached_by: BTreeMap<u64, &Edge> = BTreeMap::new();
+        let mut queue = VecDeque::from([from]);
+        while let Some(node) = queue.pop_front() {
+            if node == to {
+                let mut path = Vec::new();
+                let mut node = to;
+                while node != from {
+                    let edge = reached_by[&node];
+                    path.push(edge);
+                    node = edge.from.id;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for (&next, edge) in self.edges.get(&node).into_iter().flatten() {
+                if next != from && !reached_by.contains_key(&next) {
+                    reached_by.insert(next, edge);
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Locks the graph. Nothing panics while it is locked, so poisoning is ignored.
+fn graph() -> MutexGuard<'static, Graph> {
+    GRAPH.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// To be called by a lock before it waits to be taken. Records that each lock
+/// the current thread holds is taken before `lock`, and panics if some thread
+/// has taken them the other way round: waiting now could deadlock.
+///
+/// Does nothing in release builds.
+pub fn will_lock<L: Tracked + ?Sized>(lock: &L) {
+    if !cfg!(debug_assertions) || thread::panicking() {
+        return;
+    }
+    let to = Held::of(lock);
+    let Ok(held) = HELD.try_with(|held| held.borrow().clone()) else {
+        return;
+    };
+    if held.iter().all(|from| from.id == to.id) {
+        return;
+    }
+
+    let mut graph = graph();
+    for &from in &held {
+        if from.id == to.id || graph.contains(from.id, to.id) {
+            continue;
+        }
+        // A path back from `to` to `from` plus the new edge would be a cycle.
+        if let Some(path) = graph.path(to.id, from.id) {
+            let report = report(from, to, &path);
+            drop(graph);
+            panic!("{}", report);
+        }
+        graph.insert(Edge {
+            from,
+            to,
+            thread: thread_name(),
+            backtrace: Backtrace::force_capture(),
+        });
+    }
+}
+
+/// To be called by a lock once the current thread holds it.
+///
+/// Does nothing in release builds.
+pub fn locked<L: Tracked + ?Sized>(lock: &L) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    let held = Held::of(lock);
+    let _ = HELD.try_with(|list| list.borrow_mut().push(held));
+}
+
+/// To be called by a lock when the current thread releases it.
+///
+/// Does nothing in release builds.
+pub fn unlocked<L: Tracked + ?Sized>(lock: &L) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    let id = Held::of(lock).id;
+    // Locks may be released in any order, and an `RwLock` may be read-locked
+    // more than once, so remove the most recent entry for this lock.
+    let _ = HELD.try_with(|list| {
+        let mut list = list.borrow_mut();
+        if let Some(index) = list.iter().rposition(|held| held.id == id) {
+            list.remove(index);
+        }
+    });
+}
+
+fn thread_name() -> String {
+    thread::current().name().unwrap_or("<unnamed>").to_string()
+}
+
+/// Describes the lock orders that `from` -> `to` would close a cycle with.
+fn report(from: Held, to: Held, path: &[&Edge]) -> String {
+    let mut report = format!(
+        "possible deadlock: thread '{}' is about to lock {} while holding {},\n\
+         but these locks have been taken in the opposite order before:\n",
+        thread_name(),
+        to.name(),
+        from.name()
+    );
+    for edge in path {
+        let _ = write!(
+            report,
+            "\nthread '{}' locked {} while holding {}, at:\n{}\n",
+            edge.thread,
+            edge.to.name(),
+            edge.from.name(),
+            edge.backtrace
+        );
+    }
+    let _ = write!(
+        report,
+        "\nthread '{}' is locking {} while holding {}, at:\n{}",
+        thread_name(),
+        to.name(),
+        from.name(),
+        Backtrace::force_capture()
+    );
+    report
+}
+
+/// A `std::sync::Mutex` that lockdep watches.
+pub struct TrackedMutex<T> {
+    id: LockId,
+    inner: Mutex<T>,
+}
+
+/// A `std::sync::RwLock` that lockdep watches. Read and write locks count the
+/// same: a reader waiting behind a writer can be part of a deadlock as well.
+pub struct TrackedRwLock<T> {
+    id: LockId,
+    inner: RwLock<T>,
+}
+
+/// Wraps the guard of a `TrackedMutex` or `TrackedRwLock` and tells lockdep
+/// when it is dropped.
+pub struct TrackedGuard<'a, G> {
+    guard: G,
+    lock: &'a dyn Tracked,
+}
+
+impl<T> TrackedMutex<T> {
+    /// Creates a new, unlocked mutex protecting the given data.
+    pub const fn new(value: T) -> Self {
+        TrackedMutex {
+            id: LockId::new(),
+            inner: Mutex::new(value),
+        }
+    }
+
+    /// Acquires the mutex, like `Mutex::lock`.
+    pub fn lock(&self) -> LockResult<TrackedGuard<'_, MutexGuard<'_, T>>> {
+        will_lock(self);
+        track(self, self.inner.lock())
+    }
+
+    /// Attempts to acquire the mutex without waiting, like `Mutex::try_lock`.
+    pub fn try_lock(&self) -> TryLockResult<TrackedGuard<'_, MutexGuard<'_, T>>> {
+        try_track(self, self.inner.try_lock())
+    }
+
+    /// Consumes the mutex and returns the protected data.
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+impl<T> TrackedRwLock<T> {
+    /// Creates a new, unlocked lock protecting the given data.
+    pub const fn new(value: T) -> Self {
+        TrackedRwLock {
+            id: LockId::new(),
+            inner: RwLock::new(value),
+        }
+    }
+
+    /// Acquires shared read access, like `RwLock::read`.
+    pub fn read(&self) -> LockResult<TrackedGuard<'_, RwLockReadGuard<'_, T>>> {
+        will_lock(self);
+        track(self, self.inner.read())
+    }
+
+    /// Acquires exclusive write access, like `RwLock::write`.
+    pub fn write(&self) -> LockResult<TrackedGuard<'_, RwLockWriteGuard<'_, T>>> {
+        will_lock(self);
+        track(self, self.inner.write())
+    }
+
+    /// Attempts to acquire shared read access without waiting.
+    pub fn try_read(&self) -> TryLockResult<TrackedGuard<'_, RwLockReadGuard<'_, T>>> {
+        try_track(self, self.inner.try_read())
+    }
+
+    /// Attempts to acquire exclusive write access without waiting.
+    pub fn try_write(&self) -> TryLockResult<TrackedGuard<'_, RwLockWriteGuard<'_, T>>> {
+        try_track(self, self.inner.try_write())
+    }
+
+    /// Consumes the lock and returns the protected data.
+    pub fn into_inner(self) -> LockResult<T> {
+        self.inner.into_inner()
+    }
+}
+
+/// Wraps a guard that was just acquired, keeping a poisoned lock poisoned.
+fn track<'a, G>(lock: &'a dyn Tracked, result: LockResult<G>) -> LockResult<TrackedGuard<'a, G>> {
+    locked(lock);
+    match result {
+        Ok(guard) => Ok(TrackedGuard { guard, lock }),
+        Err(poisoned) => Err(PoisonError::new(TrackedGuard {
+            guard: poisoned.into_inner(),
+            lock,
+        })),
+    }
+}
+
+fn try_track<'a, G>(
+    lock: &'a dyn Tracked,
+    result: TryLockResult<G>,
+) -> TryLockResult<TrackedGuard<'a, G>> {
+    match result {
+        Ok(guard) => Ok(track(lock, Ok(guard))?),
+        Err(TryLockError::Poisoned(poisoned)) => Ok(track(lock, Err(poisoned))?),
+        Err(TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+    }
+}
+
+impl<T> Tracked for TrackedMutex<T> {
+    fn lock_id(&self) -> &LockId {
+        &self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "Mutex"
+    }
+}
+
+impl<T> Tracked for TrackedRwLock<T> {
+    fn lock_id(&self) -> &LockId {
+        &self.id
+    }
+
+    fn kind(&self) -> &'static str {
+        "RwLock"
+    }
+}
+
+impl<T> Lock<T> for TrackedMutex<T> {
+    type Guard<'a>
+        = TrackedGuard<'a, MutexGuard<'a, T>>
+    where
+        Self: 'a;
+
+    fn new(value: T) -> Self {
+        TrackedMutex::new(value)
+    }
+
+    fn lock(&self) -> LockResult<Self::Guard<'_>> {
+        TrackedMutex::lock(self)
+    }
+
+    fn try_lock(&self) -> TryLockResult<Self::Guard<'_>> {
+        TrackedMutex::try_lock(self)
+    }
+}
+
+impl<G> Drop for TrackedGuard<'_, G> {
+    fn drop(&mut self) {
+        // The wrapped guard releases the lock right after this.
+        unlocked(self.lock);
+    }
+}
+
+impl<G: Deref> Deref for TrackedGuard<'_, G> {
+    type Target = G::Target;
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for TrackedGuard<'_, G> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use crate::spinlock::SpinLock;
+
+    #[test]
+    fn owned_guard_released_on_another_thread_is_not_held_any_more() {
+        let a = Arc::new(SpinLock::new(()));
+        let b = Arc::new(SpinLock::new(()));
+        let guard = a.lock_owned().unwrap();
+        thread::spawn(move || drop(guard)).join().unwrap();
+
+        // We no longer hold `a`, so this must not record that `b` comes after it,
+        drop(b.lock().unwrap());
+        // and taking `a` while holding `b` is the first order seen for the two.
+        thread::spawn(move || {
+            let _b = b.lock().unwrap();
+            let _a = a.lock().unwrap();
+        })
+        .join()
+        .unwrap();
+    }
+}
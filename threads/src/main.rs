@@ -1,9 +1,27 @@
+use std::cell::RefCell;
 use std::collections::VecDeque;
+use std::panic;
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use std::sync::mpsc::{RecvError, RecvTimeoutError, TryRecvError, TrySendError};
+use std::sync::{Mutex, TryLockError};
 use std::thread;
+use std::time::{Duration, Instant};
 
+use threads::barrier::Barrier;
+use threads::channel;
+use threads::condvar::SpinCondvar;
+use threads::epoch;
+use threads::hazard;
+use threads::latch::CountDownLatch;
+use threads::lockdep::TrackedMutex;
+use threads::msqueue::MsQueue;
+use threads::reentrantlock::ReentrantSpinLock;
+use threads::rwspinlock::RwSpinLock;
+use threads::semaphore::Semaphore;
+use threads::spinlock::{MappedSpinLockGuard, OwnedSpinLockGuard, SpinLock, SpinLockGuard};
+use threads::ticketlock::TicketLock;
+use threads::treiberstack::TreiberStack;
 
 fn thread_clone() {
     let a = Arc::new([1, 2, 3]);
@@ -24,47 +42,609 @@ fn thread_clone() {
 }
 
 /// Demonstrates a multi-threaded producer-consumer pattern that gracefully terminates.
-/// The producer sends a finite number of items and then a `None` "poison pill"
-/// to signal the consumer to shut down.
+/// The producer sends a finite number of items through a bounded channel and then
+/// drops its `Sender`, which disconnects the channel and shuts the consumers down.
 fn producer_consumer_example() {
-    // The queue now holds `Option<i32>` to allow for a termination signal (None).
-    // We need to specify the type here because the compiler can't infer it from an empty VecDeque.
-    let queue = Mutex::new(VecDeque::<Option<i32>>::new());
+    // Room for only two items, so the producer blocks when the consumers fall behind.
+    let (sender, receiver) = channel::bounded::<i32>(2);
 
-    thread::scope(|s| {
-        // Consuming thread
-        let consumer_handle = s.spawn(|| loop {
-            let item_option = queue.lock().unwrap().pop_front();
-            if let Some(item) = item_option {
-                match item {
-                    // If we receive a value, process it.
-                    Some(val) => {dbg!(val);},
-                    // If we receive `None`, it's the signal to stop.
-                    None => break,
-                }
-            } else {
-                // If the queue is empty, park the thread until the producer unparks it.
-                thread::park();
-            }
-        });
+    let consumed = thread::scope(|s| {
+        // Consuming threads, each with its own clone of the receiver.
+        let consumers: Vec<_> = (0..2)
+            .map(|id| {
+                let receiver = receiver.clone();
+                s.spawn(move || {
+                    let mut items = Vec::new();
+                    // The loop ends once the channel is empty and disconnected.
+                    for val in &receiver {
+                        println!("Consumer {} got {}", id, val);
+                        items.push(val);
+                    }
+                    items
+                })
+            })
+            .collect();
+        drop(receiver);
 
         // Producing thread (the main thread in this scope)
         for i in 0..5 {
             println!("Producing {}", i);
-            queue.lock().unwrap().push_back(Some(i));
-            consumer_handle.thread().unpark(); // Wake up the consumer
-            thread::sleep(Duration::from_millis(500));
+            sender.send(i).unwrap();
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        // Dropping the last sender is the termination signal.
+        println!("Producer finished. Dropping the sender.");
+        drop(sender);
+
+        let mut consumed: Vec<i32> = consumers
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect();
+        consumed.sort();
+        consumed
+    });
+    // Every item is received exactly once, by one of the consumers.
+    assert_eq!(consumed, [0, 1, 2, 3, 4]);
+
+    // The non-blocking variants report a full, empty or disconnected channel.
+    let (sender, receiver) = channel::bounded(1);
+    sender.try_send(1).unwrap();
+    assert!(matches!(sender.try_send(2), Err(TrySendError::Full(2))));
+    assert_eq!(receiver.try_recv(), Ok(1));
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_millis(50)),
+        Err(RecvTimeoutError::Timeout)
+    );
+    drop(sender);
+    assert_eq!(receiver.recv(), Err(RecvError));
+}
+
+/// Demonstrates backing off from a `SpinLock` that another thread holds for a long time.
+/// `try_lock` gives up immediately, `lock_timeout` gives up after a deadline.
+fn spinlock_timeout_example() {
+    let lock = SpinLock::new(0);
+
+    thread::scope(|s| {
+        let guard = lock.lock().unwrap();
+        s.spawn(|| {
+            // The main thread holds the lock, so both attempts fail.
+            assert!(matches!(lock.try_lock(), Err(TryLockError::WouldBlock)));
+            let start = std::time::Instant::now();
+            assert!(matches!(
+                lock.lock_timeout(Duration::from_millis(100)),
+                Err(TryLockError::WouldBlock)
+            ));
+            println!("Gave up waiting after {:?}", start.elapsed());
+        });
+        thread::sleep(Duration::from_millis(300));
+        drop(guard);
+    });
+
+    // Nobody holds the lock any more, so it is acquired right away.
+    *lock.lock_timeout(Duration::from_millis(100)).unwrap() += 1;
+
+    // With exclusive ownership no locking is needed at all.
+    let mut lock = lock;
+    *lock.get_mut() += 1;
+    println!("Final value: {}", lock.into_inner());
+}
+
+/// Demonstrates `RwSpinLock` with writer preference: reader threads read the
+/// shared configuration over and over again, yet the writer still gets its turn.
+fn rwspinlock_example() {
+    let config = RwSpinLock::with_writer_preference(String::from("Initial Config"));
+    let writer_done = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        // A steady stream of readers. Without writer preference there is hardly
+        // ever a moment without a reader, so the writer could wait forever.
+        for i in 0..3 {
+            let (config, writer_done) = (&config, &writer_done);
+            s.spawn(move || {
+                let mut reads = 0;
+                while !writer_done.load(Ordering::Relaxed) {
+                    let current = config.read();
+                    assert!(current.ends_with("Config"));
+                    reads += 1;
+                }
+                println!("Reader Thread {} read the config {} times", i, reads);
+            });
+        }
+
+        thread::sleep(Duration::from_millis(50));
+        let start = std::time::Instant::now();
+        *config.write() = String::from("Updated Config");
+        println!("Writer got in after {:?}", start.elapsed());
+        writer_done.store(true, Ordering::Relaxed);
+    });
+
+    // An upgradable read lets us inspect the value first and only take the
+    // write lock when a change is needed, without another writer slipping in.
+    let current = config.upgradable_read();
+    if !current.starts_with("Final") {
+        let mut current = current.upgrade();
+        *current = String::from("Final Config");
+    }
+    println!("Final config value: \"{}\"", *config.read());
+}
+
+/// Demonstrates lock poisoning: a thread panics halfway through updating two
+/// values that must always add up to 100. Later callers are warned about it.
+fn spinlock_poisoning_example() {
+    let balances = SpinLock::new((60, 40));
+
+    thread::scope(|s| {
+        let result = s
+            .spawn(|| {
+                let mut balances = balances.lock().unwrap();
+                balances.0 -= 10;
+                panic!("Crashed before updating the second balance");
+            })
+            .join();
+        assert!(result.is_err());
+    });
+
+    // Just like with a `Mutex`, the next `lock()` reports the panic...
+    assert!(balances.is_poisoned());
+    let mut guard = match balances.lock() {
+        Ok(_) => unreachable!("the lock should be poisoned"),
+        Err(poisoned) => {
+            println!("Lock is poisoned, repairing the data");
+            // ...and `into_inner` recovers the guard so the data can be repaired.
+            poisoned.into_inner()
         }
+    };
+    guard.1 = 100 - guard.0;
+    drop(guard);
+    balances.clear_poison();
+
+    println!("Balances after repair: {:?}", *balances.lock().unwrap());
+}
+
+/// The shared state of a small web server, used by `spinlock_guards_example`.
+struct ServerState {
+    name: String,
+    hits: u64,
+    last_client: Option<String>,
+}
+
+/// Returns a guard for just the hit counter. Callers cannot touch the rest of
+/// the state, but the whole lock stays held until the guard is dropped.
+fn lock_hits(state: &SpinLock<ServerState>) -> MappedSpinLockGuard<'_, u64> {
+    SpinLockGuard::map(state.lock().unwrap(), |state| &mut state.hits)
+}
+
+/// Takes ownership of the `Arc` and still returns a guard. A borrowing
+/// `SpinLockGuard` could not outlive the `Arc` that this function drops.
+fn lock_state(state: Arc<SpinLock<ServerState>>) -> OwnedSpinLockGuard<ServerState> {
+    state.lock_owned().unwrap()
+}
+
+/// Demonstrates mapped guards and owned guards.
+fn spinlock_guards_example() {
+    let state = Arc::new(SpinLock::new(ServerState {
+        name: String::from("demo-server"),
+        hits: 0,
+        last_client: None,
+    }));
+
+    *lock_hits(&state) += 1;
 
-        // After sending all items, send the `None` signal to terminate the consumer.
-        println!("Producer finished. Sending termination signal.");
-        queue.lock().unwrap().push_back(None);
-        consumer_handle.thread().unpark();
+    // An owned guard is `'static`, so it can be moved into another thread,
+    // which releases the lock there. Meanwhile, this thread waits for it.
+    let guard = lock_state(Arc::clone(&state));
+    let server = thread::spawn(move || {
+        let mut guard = guard;
+        thread::sleep(Duration::from_millis(10));
+        guard.hits += 1;
+        println!("{} served a request on another thread", guard.name);
     });
+
+    // `try_map` hands the guard back when the closure finds nothing to map to.
+    let guard = state.lock().unwrap();
+    match SpinLockGuard::try_map(guard, |state| state.last_client.as_mut()) {
+        Ok(client) => println!("Last client: {}", *client),
+        Err(guard) => println!("No clients yet, but {} hits", guard.hits),
+    }
+    server.join().unwrap();
+}
+
+/// Logs a message and, for nested messages, recursively logs the parent first.
+/// Every level takes the lock again while the caller still holds it.
+fn log_nested(log: &ReentrantSpinLock<RefCell<Vec<String>>>, depth: u32) {
+    let guard = log.lock();
+    if depth > 0 {
+        log_nested(log, depth - 1);
+    }
+    guard
+        .borrow_mut()
+        .push(format!("message at depth {}", depth));
+}
+
+/// Demonstrates `ReentrantSpinLock`, and what a plain `SpinLock` does instead.
+fn reentrant_spinlock_example() {
+    let log = ReentrantSpinLock::new(RefCell::new(Vec::new()));
+    log_nested(&log, 3);
+    println!("Log: {:?}", log.lock().borrow());
+
+    // Locking a plain `SpinLock` twice on one thread would spin forever. Debug
+    // builds detect this and panic with a clear message instead.
+    if cfg!(debug_assertions) {
+        let result = thread::spawn(|| {
+            let lock = SpinLock::new(0);
+            let _first = lock.lock().unwrap();
+            let _second = lock.lock().unwrap();
+        })
+        .join();
+        assert!(result.is_err());
+    }
+}
+
+/// A producer-consumer queue with several producers and several consumers.
+/// Consumers sleep on a `SpinCondvar` instead of being unparked by handle, so
+/// nobody needs to know which thread is waiting.
+fn condvar_queue_example() {
+    // The number of producers still running lives under the same lock as the
+    // items: a consumer checks both before it sleeps, so the last producer must
+    // not be able to finish in between.
+    let queue = SpinLock::new((VecDeque::new(), 2));
+    let not_empty = SpinCondvar::new();
+    let consumed = SpinLock::new(Vec::new());
+
+    thread::scope(|s| {
+        for producer in 0..2 {
+            let (queue, not_empty) = (&queue, &not_empty);
+            s.spawn(move || {
+                for i in 0..5 {
+                    queue.lock().unwrap().0.push_back(producer * 100 + i);
+                    not_empty.notify_one();
+                    thread::sleep(Duration::from_millis(10));
+                }
+                queue.lock().unwrap().1 -= 1;
+                // Every consumer has to re-check whether there is more to come.
+                not_empty.notify_all();
+            });
+        }
+
+        for consumer in 0..3 {
+            let (queue, not_empty, consumed) = (&queue, &not_empty, &consumed);
+            s.spawn(move || {
+                loop {
+                    let guard = queue.lock().unwrap();
+                    // Sleep while there is nothing to do but more items may still come.
+                    let mut guard = not_empty
+                        .wait_while(guard, |(items, producers_left)| {
+                            items.is_empty() && *producers_left > 0
+                        })
+                        .unwrap();
+                    match guard.0.pop_front() {
+                        Some(item) => {
+                            drop(guard);
+                            println!("Consumer {} got {}", consumer, item);
+                            consumed.lock().unwrap().push(item);
+                        }
+                        // The queue is empty and all producers are done.
+                        None => break,
+                    }
+                }
+            });
+        }
+    });
+
+    // Nobody is left to notify us, so a timed wait runs out.
+    let (_queue, result) = not_empty
+        .wait_timeout(queue.lock().unwrap(), Duration::from_millis(10))
+        .unwrap();
+    assert!(result.timed_out());
+
+    let mut consumed = consumed.into_inner();
+    consumed.sort();
+    assert_eq!(consumed, [0, 1, 2, 3, 4, 100, 101, 102, 103, 104]);
+}
+
+/// Times several producers pushing while several consumers pop, until every
+/// item has come out again. `tests/lockfree.rs` checks that each one does so
+/// exactly once.
+fn time_queue<Q: Sync>(
+    name: &str,
+    queue: Q,
+    push: impl Fn(&Q, u64) + Sync,
+    pop: impl Fn(&Q) -> Option<u64> + Sync,
+) {
+    const PRODUCERS: u64 = 4;
+    const CONSUMERS: usize = 4;
+    const ITEMS: u64 = 50_000;
+    let total = PRODUCERS * ITEMS;
+    let popped = AtomicU64::new(0);
+
+    let start = Instant::now();
+    thread::scope(|s| {
+        for producer in 0..PRODUCERS {
+            let (queue, push) = (&queue, &push);
+            s.spawn(move || {
+                for i in 0..ITEMS {
+                    push(queue, producer * ITEMS + i);
+                }
+            });
+        }
+        for _ in 0..CONSUMERS {
+            s.spawn(|| {
+                while popped.load(Ordering::Relaxed) < total {
+                    match pop(&queue) {
+                        Some(_) => {
+                            popped.fetch_add(1, Ordering::Relaxed);
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+            });
+        }
+    });
+    println!(
+        "{}: {} items passed through in {:?}",
+        name,
+        total,
+        start.elapsed()
+    );
+}
+
+/// Compares the lock-free `MsQueue` and `TreiberStack` with the
+/// `Mutex<VecDeque>` that `producer_consumer_example` used to hand-roll.
+fn lockfree_queue_example() {
+    time_queue(
+        "Mutex<VecDeque>",
+        Mutex::new(VecDeque::new()),
+        |queue, item| queue.lock().unwrap().push_back(item),
+        |queue| queue.lock().unwrap().pop_front(),
+    );
+    time_queue(
+        "MsQueue",
+        MsQueue::new(),
+        |queue, item| queue.push(item),
+        |queue| queue.pop(),
+    );
+    time_queue(
+        "TreiberStack",
+        TreiberStack::new(),
+        |stack, item| stack.push(item),
+        |stack| stack.pop(),
+    );
+
+    // One is first-in first-out, the other last-in first-out.
+    let (queue, stack) = (MsQueue::new(), TreiberStack::new());
+    for i in 1..=3 {
+        queue.push(i);
+        stack.push(i);
+    }
+    println!(
+        "After pushing 1, 2, 3: the queue pops {:?}, the stack pops {:?}",
+        queue.pop(),
+        stack.pop()
+    );
+
+    // Free the nodes that are still waiting for a hazard pointer scan.
+    hazard::reclaim();
+}
+
+/// A value that counts its own drops, to see when the epoch collector frees it.
+struct DropCounter {
+    value: u64,
+    drops: Arc<AtomicU64>,
+}
+
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        self.drops.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Replaces a shared value while other threads read it without any lock. The
+/// old values are handed to the epoch collector, which must not free them while
+/// a reader is still pinned (see the tests of `epoch`).
+fn epoch_example() {
+    let drops = Arc::new(AtomicU64::new(0));
+    let counted = |value| {
+        Box::into_raw(Box::new(DropCounter {
+            value,
+            drops: Arc::clone(&drops),
+        }))
+    };
+    let shared = AtomicPtr::new(counted(0));
+
+    // Many readers and a writer that replaces the value as fast as it can.
+    let stop = AtomicBool::new(false);
+    thread::scope(|s| {
+        for reader in 0..3 {
+            let (shared, stop) = (&shared, &stop);
+            s.spawn(move || {
+                let (mut last, mut reads) = (0, 0);
+                while !stop.load(Ordering::Relaxed) {
+                    let _guard = epoch::pin();
+                    // Safety: We are pinned, so the value cannot be freed under us.
+                    let value = unsafe { (*shared.load(Ordering::Acquire)).value };
+                    assert!(value >= last, "read a stale or freed value");
+                    (last, reads) = (value, reads + 1);
+                }
+                println!(
+                    "Reader {} read {} values, the last one was {}",
+                    reader, reads, last
+                );
+            });
+        }
+        for value in 1..=10_000 {
+            let guard = epoch::pin();
+            let old = shared.swap(counted(value), Ordering::AcqRel);
+            // Safety: `old` is unreachable now and was allocated with `Box`.
+            unsafe { guard.defer_destroy(old) };
+            drop(guard);
+            // Give the readers a chance to run, even on a single core.
+            if value % 100 == 0 {
+                thread::yield_now();
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+    });
+    for _ in 0..3 {
+        epoch::flush();
+    }
+    // Every replaced value has been freed exactly once, and only those.
+    assert_eq!(drops.load(Ordering::Relaxed), 10_000);
+    assert!(!epoch::is_pinned());
+    // Safety: Nobody else can reach the current value any more.
+    drop(unsafe { Box::from_raw(shared.into_inner()) });
+}
+
+/// Coordinates threads with a `Barrier`, a `CountDownLatch` and a `Semaphore`.
+fn coordination_example() {
+    // Four threads go through three rounds in lockstep: nobody starts a round
+    // before everybody has finished the previous one.
+    let barrier = Barrier::new(4);
+    let finished = SpinLock::new(Vec::new());
+    let leaders = AtomicU64::new(0);
+    thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for round in 0..3 {
+                    finished.lock().unwrap().push(round);
+                    if barrier.wait().is_leader() {
+                        leaders.fetch_add(1, Ordering::Relaxed);
+                    }
+                    let finished = finished.lock().unwrap();
+                    assert_eq!(finished.iter().filter(|&&r| r == round).count(), 4);
+                }
+            });
+        }
+    });
+    assert_eq!(leaders.into_inner(), 3);
+    println!("Barrier: 4 threads went through 3 rounds together");
+
+    // The main thread waits until all workers have started up.
+    let ready = CountDownLatch::new(3);
+    thread::scope(|s| {
+        for worker in 0..3 {
+            let ready = &ready;
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10 * worker));
+                ready.count_down();
+            });
+        }
+        ready.wait();
+        assert_eq!(ready.count(), 0);
+        println!("CountDownLatch: all workers are ready");
+    });
+    assert!(!CountDownLatch::new(1).wait_timeout(Duration::from_millis(10)));
+
+    // At most two of the six threads are inside at any time.
+    let semaphore = Semaphore::new(2);
+    let inside = AtomicU64::new(0);
+    let most_inside = AtomicU64::new(0);
+    thread::scope(|s| {
+        for _ in 0..6 {
+            s.spawn(|| {
+                let _permit = semaphore.acquire();
+                let now_inside = inside.fetch_add(1, Ordering::SeqCst) + 1;
+                most_inside.fetch_max(now_inside, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                inside.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+    assert!(most_inside.load(Ordering::SeqCst) <= 2);
+    println!(
+        "Semaphore: at most {} threads were inside at once",
+        most_inside.load(Ordering::SeqCst)
+    );
+
+    // Permits come back when the guard is dropped.
+    let both = semaphore.acquire_many(2);
+    assert!(semaphore.try_acquire().is_none());
+    drop(both);
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+/// Runs `f` on a new thread called `name` and waits for it to finish.
+fn run_as(name: &str, f: impl FnOnce() + Send) -> thread::Result<()> {
+    thread::scope(|s| {
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn_scoped(s, f)
+            .unwrap()
+            .join()
+    })
+}
+
+/// Three threads each take two of three locks, one after the other, so they
+/// never actually deadlock here. But together their lock orders form a cycle,
+/// and with unlucky timing each would wait for the next one forever. Debug
+/// builds stop the thread that closes the cycle before it starts waiting.
+fn lockdep_example() {
+    let accounts = SpinLock::new(100);
+    let audit_log = TrackedMutex::new(Vec::new());
+    let fees = TicketLock::new(0);
+
+    // The report carries backtraces; only its summary is printed below.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    // accounts -> audit_log
+    run_as("teller", || {
+        let balance = accounts.lock().unwrap();
+        audit_log
+            .lock()
+            .unwrap()
+            .push(format!("balance {}", *balance));
+    })
+    .unwrap();
+    // audit_log -> fees
+    run_as("auditor", || {
+        let log = audit_log.lock().unwrap();
+        *fees.lock() += log.len();
+    })
+    .unwrap();
+    // fees -> accounts closes the cycle.
+    let result = run_as("billing", || {
+        let fees = fees.lock();
+        *accounts.lock().unwrap() -= *fees;
+    });
+    panic::set_hook(default_hook);
+
+    if cfg!(debug_assertions) {
+        let report = result.unwrap_err();
+        let report = report.downcast_ref::<String>().unwrap();
+        for line in report.lines().filter(|line| !line.starts_with(' ')) {
+            if !line.is_empty() {
+                println!("{}", line.trim_end_matches(", at:"));
+            }
+        }
+    } else {
+        result.unwrap();
+        println!("lockdep only checks lock orders in debug builds");
+    }
 }
 
 fn main() {
     thread_clone();
     println!("\n--- Running Producer-Consumer Example ---");
     producer_consumer_example();
+    println!("\n--- Running SpinLock Timeout Example ---");
+    spinlock_timeout_example();
+    println!("\n--- Running RwSpinLock Example ---");
+    rwspinlock_example();
+    println!("\n--- Running SpinLock Poisoning Example ---");
+    spinlock_poisoning_example();
+    println!("\n--- Running SpinLock Mapped and Owned Guards Example ---");
+    spinlock_guards_example();
+    println!("\n--- Running ReentrantSpinLock Example ---");
+    reentrant_spinlock_example();
+    println!("\n--- Running SpinCondvar Queue Example ---");
+    condvar_queue_example();
+    println!("\n--- Running Lock-Free Queue Example ---");
+    lockfree_queue_example();
+    println!("\n--- Running Epoch Reclamation Example ---");
+    epoch_example();
+    println!("\n--- Running Barrier, Latch and Semaphore Example ---");
+    coordination_example();
+    println!("\n--- Running Lockdep Example ---");
+    lockdep_example();
 }
@@ -0,0 +1,201 @@
+use std::cell::UnsafeCell;
+use std::hint;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use std::sync::{LockResult, TryLockError, TryLockResult};
+
+use crate::backoff::{Backoff, SpinThenYield};
+use crate::lock::Lock;
+use crate::lockdep::{self, LockId, Tracked};
+
+/// A queue node owned by one waiting (or lock-holding) thread.
+struct McsNode {
+    /// The node of the thread that queued up behind us, if any.
+    next: AtomicPtr<McsNode>,
+    /// `true` while our predecessor still holds the lock.
+    locked: AtomicBool,
+}
+
+/// A fair queue lock (Mellor-Crummey and Scott).
+///
+/// Waiters form a linked list in FIFO order. Each waiter spins on the `locked`
+/// flag of its *own* node, and the releasing thread clears the flag of exactly
+/// one successor. Under contention every core therefore spins on a different
+/// cache line, instead of all of them hammering the single flag of a `SpinLock`
+/// or the shared counter of a `TicketLock`.
+pub struct McsLock<T> {
+    /// The last node in the queue, or null if the lock is free.
+    tail: AtomicPtr<McsNode>,
+    /// The lock's node in the lock-order graph of `lockdep`.
+    dep: LockId,
+    value: UnsafeCell<T>,
+}
+
+/// A guard that provides access to the data locked by an `McsLock`.
+/// It owns the queue node of the current holder; dropping it hands the lock
+/// to the next node in the queue.
+pub struct McsLockGuard<'a, T> {
+    lock: &'a McsLock<T>,
+    /// Heap-allocated so that its address stays stable while the guard moves.
+    node: *mut McsNode,
+}
+
+unsafe impl<T: Send> Send for McsLock<T> {}
+unsafe impl<T: Send> Sync for McsLock<T> {}
+
+impl<T> McsLock<T> {
+    /// Creates a new `McsLock` protecting the given data.
+    pub const fn new(value: T) -> Self {
+        Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            dep: LockId::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    fn new_node() -> *mut McsNode {
+        Box::into_raw(Box::new(McsNode {
+            next: AtomicPtr::new(ptr::null_mut()),
+            locked: AtomicBool::new(true),
+        }))
+    }
+
+    /// Acquires the lock, queueing up behind every thread that arrived earlier.
+    pub fn lock(&self) -> McsLockGuard<'_, T> {
+        lockdep::will_lock(self);
+        let node = Self::new_node();
+
+        // Append ourselves to the queue. `AcqRel`: `Release` publishes our node
+        // to the next waiter, `Acquire` lets us see our predecessor's node (or,
+        // if the queue was empty, the data written by the last holder).
+        let prev = self.tail.swap(node, Ordering::AcqRel);
+        if !prev.is_null() {
+            // Safety: A node stays alive until its owner has handed the lock on,
+            // and that cannot happen before we have linked ourselves in here.
+            unsafe { (*prev).next.store(node, Ordering::Release) };
+            // Spin on our *own* flag until the predecessor clears it. Like in
+            // `TicketLock::lock`, we start yielding after a while.
+            let mut backoff = SpinThenYield::<100>::default();
+            // Safety: `node` is ours until the guard is dropped.
+            while unsafe { (*node).locked.load(Ordering::Acquire) } {
+                backoff.snooze();
+            }
+        }
+
+        lockdep::locked(self);
+        McsLockGuard { lock: self, node }
+    }
+
+    /// Attempts to acquire the lock without waiting.
+    /// Only succeeds if nobody holds the lock or is queued for it.
+    pub fn try_lock(&self) -> Option<McsLockGuard<'_, T>> {
+        let node = Self::new_node();
+        match self.tail.compare_exchange(
+            ptr::null_mut(),
+            node,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => {
+                lockdep::locked(self);
+                Some(McsLockGuard { lock: self, node })
+            }
+            Err(_) => {
+                // Safety: The node was never published, so we still own it.
+                drop(unsafe { Box::from_raw(node) });
+                None
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the protected data.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Consumes the lock and returns the protected data.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T> Tracked for McsLock<T> {
+    fn lock_id(&self) -> &LockId {
+        &self.dep
+    }
+
+    fn kind(&self) -> &'static str {
+        "McsLock"
+    }
+}
+
+impl<T> Lock<T> for McsLock<T> {
+    type Guard<'a>
+        = McsLockGuard<'a, T>
+    where
+        Self: 'a;
+
+    fn new(value: T) -> Self {
+        McsLock::new(value)
+    }
+
+    fn lock(&self) -> LockResult<Self::Guard<'_>> {
+        Ok(McsLock::lock(self))
+    }
+
+    fn try_lock(&self) -> TryLockResult<Self::Guard<'_>> {
+        McsLock::try_lock(self).ok_or(TryLockError::WouldBlock)
+    }
+}
+
+/// Hands the lock to our successor, or marks the lock as free if there is none.
+impl<T> Drop for McsLockGuard<'_, T> {
+    fn drop(&mut self) {
+        lockdep::unlocked(self.lock);
+        let node = self.node;
+        // Safety: We own `node` until it is freed at the end of this function.
+        unsafe {
+            let mut next = (*node).next.load(Ordering::Acquire);
+            if next.is_null() {
+                // Nobody seems to be waiting: try to swing `tail` back to null.
+                if self
+                    .lock
+                    .tail
+                    .compare_exchange(node, ptr::null_mut(), Ordering::Release, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    drop(Box::from_raw(node));
+                    return;
+                }
+                // A new waiter has already swapped itself into `tail` but has not
+                // linked itself to our node yet. Wait for the link to appear.
+                loop {
+                    next = (*node).next.load(Ordering::Acquire);
+                    if !next.is_null() {
+                        break;
+                    }
+                    hint::spin_loop();
+                }
+            }
+            // Wake exactly one successor. `Release` publishes our writes to it.
+            (*next).locked.store(false, Ordering::Release);
+            drop(Box::from_raw(node));
+        }
+    }
+}
+
+impl<T> Deref for McsLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: Only the thread at the head of the queue holds a guard.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for McsLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: Only the thread at the head of the queue holds a guard.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
@@ -0,0 +1,1446 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::hint;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{self, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+/// How many stores to each atomic are remembered. A load can read any of them
+/// that the memory model allows, so more history means more behaviours but
+/// also many more executions.
+const HISTORY: usize = 4;
+
+/// Schedules are written with one base-36 digit per choice, so a choice can
+/// never be between more than 36 threads.
+const MAX_THREADS: usize = 36;
+
+/// Every execution gets its own number, so that atomics and cells can tell
+/// whether they have been seen in the current execution yet.
+static NEXT_EXECUTION: AtomicU64 = AtomicU64::new(1);
+
+const OUTSIDE: &str = "only threads of a model check can do this";
+
+thread_local! {
+    /// The execution the current thread belongs to, and its number in it.
+    static CONTEXT: RefCell<Option<(Arc<Execution>, usize)>> = const { RefCell::new(None) };
+}
+
+fn context() -> Option<(Arc<Execution>, usize)> {
+    // During thread-local destruction, the thread no longer takes part.
+    CONTEXT
+        .try_with(|context| context.borrow().clone())
+        .ok()
+        .flatten()
+}
+
+/// A model checker for small concurrent tests, in the spirit of `loom`.
+///
+/// Running a test on real threads only ever shows the interleavings that the
+/// OS and the CPU happen to produce, so a missing `Release` can go unnoticed for
+/// millions of runs. `check` instead runs the test over and over, each time
+/// with a different schedule, until it has seen every way the threads can
+/// interleave, and every older value each load is allowed to return under the
+/// C++/Rust memory model. The test fails as soon as any execution panics,
+/// deadlocks, spins forever, or has a data race on an `UnsafeCell`.
+///
+/// Only one thread runs at a time, and threads can only be switched at the
+/// operations of this module: the atomics, `fence`, `UnsafeCell::get`,
+/// `spawn`, `JoinHandle::join`, `park` and friends. Tests use these instead of
+/// the `std` versions; with the `model` cargo feature, `SpinLock`, the backoff
+/// strategies and `SpinCondvar` use them as well. Outside a model check they
+/// simply behave like their `std` counterparts.
+///
+/// A failure comes with its schedule, a short string of choices, which `replay`
+/// takes to run that one execution again and trace every step of it.
+///
+/// To keep the number of executions down, the checker only preempts a thread
+/// that could go on running a few times per execution (`preemption_bound`).
+/// Most concurrency bugs need only one or two preemptions. A thread that spins
+/// or yields always lets the other threads go first, and reads the newest
+/// values afterwards. Further simplifications: `SeqCst` is treated as `AcqRel`
+/// plus a global order of `SeqCst` operations and fences, `compare_exchange_weak`
+/// never fails spuriously, and `park` never wakes up spuriously.
+#[derive(Debug, Clone)]
+pub struct Checker {
+    preemption_bound: usize,
+    max_steps: usize,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checker {
+    /// Creates a checker that allows two preemptions per execution.
+    pub fn new() -> Self {
+        Self {
+            preemption_bound: 2,
+            max_steps: 10_000,
+        }
+    }
+
+    /// Sets how often a thread may be switched out while it could go on.
+    /// Each additional preemption multiplies the number of executions.
+    pub fn preemption_bound(mut self, bound: usize) -> Self {
+        self.preemption_bound = bound;
+        self
+    }
+
+    /// Sets after how many steps an execution counts as spinning forever.
+    pub fn max_steps(mut self, steps: usize) -> Self {
+        self.max_steps = steps;
+        self
+    }
+
+    /// Runs `test` in every execution the checker can think of. Returns how many
+    /// executions there were, or the first one that failed.
+    pub fn check<F>(&self, test: F) -> Result<usize, Failure>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let test: Arc<dyn Fn() + Send + Sync> = Arc::new(test);
+        let mut path = Path::default();
+        let mut executions = 0;
+        loop {
+            executions += 1;
+            let (explored, failure, _) = self.execute(path, &test, false);
+            path = explored;
+            if let Some(message) = failure {
+                return Err(Failure {
+                    message,
+                    schedule: path.schedule(),
+                    executions,
+                    trace: Vec::new(),
+                });
+            }
+            if !path.advance() {
+                return Ok(executions);
+            }
+        }
+    }
+
+    /// Runs `test` once, following `schedule` from a `Failure`, and records a
+    /// trace of every step. The checker must have the same settings as the one
+    /// that found the schedule.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `schedule` contains anything but digits and lowercase letters.
+    pub fn replay<F>(&self, schedule: &str, test: F) -> Result<(), Failure>
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let test: Arc<dyn Fn() + Send + Sync> = Arc::new(test);
+        let (path, failure, trace) = self.execute(Path::replay(schedule), &test, true);
+        match failure {
+            Some(message) => Err(Failure {
+                message,
+                schedule: path.schedule(),
+                executions: 1,
+                trace,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs one execution along `path`, and returns the path it took.
+    fn execute(
+        &self,
+        path: Path,
+        test: &Arc<dyn Fn() + Send + Sync>,
+        trace: bool,
+    ) -> (Path, Option<String>, Vec<String>) {
+        let execution = Arc::new(Execution {
+            state: Mutex::new(State::new(self, path, trace)),
+            turn: Condvar::new(),
+            threads: Mutex::new(Vec::new()),
+        });
+        execution.start(0, {
+            let test = Arc::clone(test);
+            move || test()
+        });
+
+        let mut state = execution.lock();
+        while !state.done && state.failure.is_none() {
+            state = execution
+                .turn
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        drop(state);
+        // Stopped threads unwind on their own; wait until they are all gone.
+        loop {
+            let thread = execution
+                .threads
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .pop();
+            let Some(thread) = thread else { break };
+            let _ = thread.join();
+        }
+
+        let mut state = execution.lock();
+        (
+            mem::take(&mut state.path),
+            state.failure.take(),
+            state.trace.take().unwrap_or_default(),
+        )
+    }
+}
+
+/// Checks `test` with the default `Checker`.
+///
+/// # Panics
+///
+/// Panics with the `Failure` if any execution fails.
+pub fn check<F>(test: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    if let Err(failure) = Checker::new().check(test) {
+        panic!("{failure}");
+    }
+}
+
+/// An execution in which the test failed.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    message: String,
+    schedule: String,
+    executions: usize,
+    trace: Vec<String>,
+}
+
+impl Failure {
+    /// Describes what went wrong.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The choices that lead to this failure, for `Checker::replay`.
+    pub fn schedule(&self) -> &str {
+        &self.schedule
+    }
+
+    /// Every step of the execution, one line each. Only recorded by `replay`.
+    pub fn trace(&self) -> &[String] {
+        &self.trace
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\nin execution {}, replay it with the schedule \"{}\"",
+            self.message, self.executions, self.schedule
+        )?;
+        for step in &self.trace {
+            write!(f, "\n  {step}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for Failure {}
+
+/// The choices made in an execution, as far as it got. Exploring is a
+/// depth-first search: the next execution makes the same choices, except that
+/// the last one that has alternatives left takes the next alternative.
+#[derive(Default)]
+struct Path {
+    choices: Vec<Choice>,
+    /// How many choices the current execution has made so far.
+    pos: usize,
+    /// Set when the choices come from a schedule, which does not record how
+    /// many alternatives each one had.
+    replay: bool,
+}
+
+struct Choice {
+    taken: usize,
+    options: usize,
+}
+
+impl Path {
+    fn replay(schedule: &str) -> Path {
+        let choices = schedule
+            .chars()
+            .map(|c| c.to_digit(36).filter(|_| !c.is_ascii_uppercase()))
+            .map(|taken| {
+                let taken = taken.unwrap_or_else(|| panic!("{schedule:?} is not a schedule"));
+                Choice {
+                    taken: taken as usize,
+                    options: 0,
+                }
+            })
+            .collect();
+        Path {
+            choices,
+            pos: 0,
+            replay: true,
+        }
+    }
+
+    /// Picks one of `options` alternatives, numbered from 0.
+    fn choose(&mut self, options: usize) -> Result<usize, String> {
+        if options <= 1 {
+            return Ok(0);
+        }
+        let taken = match self.choices.get_mut(self.pos) {
+            Some(choice) if self.replay => {
+                if choice.taken >= options {
+                    return Err("the schedule does not belong to this test".to_string());
+                }
+                choice.options = options;
+                choice.taken
+            }
+            Some(choice) => {
+                if choice.options != options {
+                    return Err("the test took a different turn on the same schedule; \
+                                it must not depend on anything but the schedule"
+                        .to_string());
+                }
+                choice.taken
+            }
+            None => {
+                self.choices.push(Choice { taken: 0, options });
+                0
+            }
+        };
+        self.pos += 1;
+        Ok(taken)
+    }
+
+    /// Moves on to the next path. Returns `false` when all have been explored.
+    fn advance(&mut self) -> bool {
+        self.choices.truncate(self.pos);
+        self.pos = 0;
+        while let Some(last) = self.choices.last_mut() {
+            if last.taken + 1 < last.options {
+                last.taken += 1;
+                return true;
+            }
+            self.choices.pop();
+        }
+        false
+    }
+
+    /// The choices made so far, one digit each.
+    fn schedule(&self) -> String {
+        self.choices[..self.pos]
+            .iter()
+            .map(|choice| char::from_digit(choice.taken as u32, 36).expect("at most 36 options"))
+            .collect()
+    }
+}
+
+/// A vector clock: for every thread, how many of its steps happened before.
+#[derive(Clone, Default)]
+struct VClock(Vec<u32>);
+
+impl VClock {
+    fn get(&self, thread: usize) -> u32 {
+        self.0.get(thread).copied().unwrap_or(0)
+    }
+
+    /// Counts another step of `thread`, and returns its number.
+    fn tick(&mut self, thread: usize) -> u32 {
+        if self.0.len() <= thread {
+            self.0.resize(thread + 1, 0);
+        }
+        self.0[thread] += 1;
+        self.0[thread]
+    }
+
+    fn join(&mut self, other: &VClock) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+        for (mine, theirs) in self.0.iter_mut().zip(&other.0) {
+            *mine = (*mine).max(*theirs);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Runnable,
+    Joining(usize),
+    Parked,
+    Finished,
+}
+
+struct ThreadState {
+    status: Status,
+    /// What happened before the thread's next step.
+    clock: VClock,
+    /// The clock at the last `Release` fence, which `Relaxed` stores pass on.
+    release_fence: VClock,
+    /// What `Relaxed` loads have read, which the next `Acquire` fence takes in.
+    acquire_pending: VClock,
+    /// The newest store to each atomic the thread has seen. It can never read
+    /// an older one again.
+    last_seen: HashMap<usize, u64>,
+    /// Set when the thread yields or spins, until another thread stores
+    /// something. Other threads go first in the meantime.
+    yielded: bool,
+    /// Set when the thread yields or spins, until it stores something itself.
+    /// Its loads read the newest values in the meantime, as stores become
+    /// visible to a waiting thread eventually.
+    fresh: bool,
+    /// The token of `unpark`, with the clock of the thread that set it.
+    unpark: Option<VClock>,
+}
+
+impl ThreadState {
+    fn new(clock: VClock) -> Self {
+        Self {
+            status: Status::Runnable,
+            clock,
+            release_fence: VClock::default(),
+            acquire_pending: VClock::default(),
+            last_seen: HashMap::new(),
+            yielded: false,
+            fresh: false,
+            unpark: None,
+        }
+    }
+}
+
+/// An atomic or an `UnsafeCell` that has been used in the execution.
+enum Object {
+    Atomic(VecDeque<Store>),
+    /// The last access to the cell.
+    Cell(Option<Access>),
+}
+
+/// A store to an atomic. The first one of each atomic is its value when the
+/// execution first used it, which happens before everything.
+struct Store {
+    /// The position in the atomic's modification order.
+    seq: u64,
+    value: u64,
+    /// The thread and the number of its step, unless it is the first store.
+    by: Option<(usize, u32)>,
+    /// What a thread that reads this store with `Acquire` synchronizes with.
+    sync: VClock,
+}
+
+impl Store {
+    fn happens_before(&self, clock: &VClock) -> bool {
+        self.by
+            .is_none_or(|(thread, step)| clock.get(thread) >= step)
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Access {
+    thread: usize,
+    step: u32,
+}
+
+struct State {
+    execution: u64,
+    threads: Vec<ThreadState>,
+    /// The thread that may run.
+    active: usize,
+    path: Path,
+    preemptions: usize,
+    preemption_bound: usize,
+    steps: usize,
+    max_steps: usize,
+    objects: Vec<Object>,
+    /// The clock of the global order of `SeqCst` operations.
+    seq_cst: VClock,
+    failure: Option<String>,
+    done: bool,
+    trace: Option<Vec<String>>,
+}
+
+fn acquires(order: Ordering) -> bool {
+    matches!(
+        order,
+        Ordering::Acquire | Ordering::AcqRel | Ordering::SeqCst
+    )
+}
+
+fn releases(order: Ordering) -> bool {
+    matches!(
+        order,
+        Ordering::Release | Ordering::AcqRel | Ordering::SeqCst
+    )
+}
+
+impl State {
+    fn new(checker: &Checker, path: Path, trace: bool) -> Self {
+        Self {
+            execution: NEXT_EXECUTION.fetch_add(1, Ordering::Relaxed),
+            threads: vec![ThreadState::new(VClock::default())],
+            active: 0,
+            path,
+            preemptions: 0,
+            preemption_bound: checker.preemption_bound,
+            steps: 0,
+            max_steps: checker.max_steps,
+            objects: Vec::new(),
+            seq_cst: VClock::default(),
+            failure: None,
+            done: false,
+            trace: trace.then(Vec::new),
+        }
+    }
+
+    fn log(&mut self, thread: usize, step: impl FnOnce() -> String) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(format!("thread {thread}: {}", step()));
+        }
+    }
+
+    /// Returns the number of the object with the given tag, registering it if
+    /// this is the first time the execution sees it.
+    fn object(&mut self, tag: &AtomicU64, create: impl FnOnce() -> Object) -> usize {
+        let current = tag.load(Ordering::Relaxed);
+        if current >> 32 == self.execution {
+            return (current & u64::from(u32::MAX)) as usize - 1;
+        }
+        self.objects.push(create());
+        let index = self.objects.len() - 1;
+        tag.store(
+            (self.execution << 32) | (index as u64 + 1),
+            Ordering::Relaxed,
+        );
+        index
+    }
+
+    fn atomic(&mut self, tag: &AtomicU64, value: &AtomicU64) -> usize {
+        self.object(tag, || {
+            Object::Atomic(VecDeque::from([Store {
+                seq: 0,
+                value: value.load(Ordering::Relaxed),
+                by: None,
+                sync: VClock::default(),
+            }]))
+        })
+    }
+
+    fn stores(&self, object: usize) -> &VecDeque<Store> {
+        match &self.objects[object] {
+            Object::Atomic(stores) => stores,
+            Object::Cell(_) => unreachable!("an UnsafeCell is not an atomic"),
+        }
+    }
+
+    /// Picks the thread that runs next, or `None` if all have finished.
+    fn pick(&mut self, me: usize) -> Result<Option<usize>, String> {
+        self.steps += 1;
+        if self.steps > self.max_steps {
+            return Err(format!(
+                "the execution took more than {} steps; a thread may be spinning forever",
+                self.max_steps
+            ));
+        }
+        let runnable = |thread: &ThreadState| thread.status == Status::Runnable;
+        let current = runnable(&self.threads[me]) && !self.threads[me].yielded;
+        let others: Vec<usize> = (0..self.threads.len())
+            .filter(|&t| t != me && runnable(&self.threads[t]) && !self.threads[t].yielded)
+            .collect();
+        let candidates = if current {
+            let mut candidates = vec![me];
+            if self.preemptions < self.preemption_bound {
+                candidates.extend(others);
+            }
+            candidates
+        } else if !others.is_empty() {
+            others
+        } else {
+            // Everybody is spinning. Taking turns, without a choice, lets each
+            // of them see the newest values, so that only a real livelock goes
+            // on until `max_steps`.
+            let n = self.threads.len();
+            (1..=n)
+                .map(|i| (me + i) % n)
+                .find(|&t| runnable(&self.threads[t]))
+                .into_iter()
+                .collect()
+        };
+
+        if candidates.is_empty() {
+            if self.threads.iter().all(|t| t.status == Status::Finished) {
+                return Ok(None);
+            }
+            return Err(self.deadlock());
+        }
+        let next = candidates[self.path.choose(candidates.len())?];
+        if current && next != me {
+            self.preemptions += 1;
+        }
+        Ok(Some(next))
+    }
+
+    fn deadlock(&self) -> String {
+        let waiting: Vec<String> = (0..self.threads.len())
+            .filter_map(|t| match self.threads[t].status {
+                Status::Joining(other) => Some(format!("thread {t} waits for thread {other}")),
+                Status::Parked => Some(format!("thread {t} is parked")),
+                _ => None,
+            })
+            .collect();
+        format!("deadlock: {}", waiting.join(", "))
+    }
+
+    /// Lets other threads go first, see `ThreadState::yielded`.
+    fn progress(&mut self) {
+        for thread in &mut self.threads {
+            thread.yielded = false;
+        }
+    }
+
+    fn seq_cst_before(&mut self, me: usize, order: Ordering) {
+        if order == Ordering::SeqCst {
+            self.threads[me].clock.join(&self.seq_cst);
+        }
+    }
+
+    fn seq_cst_after(&mut self, me: usize, order: Ordering) {
+        if order == Ordering::SeqCst {
+            self.seq_cst.join(&self.threads[me].clock);
+        }
+    }
+
+    /// Returns the index of the oldest store the thread may still read.
+    fn oldest_readable(&self, me: usize, object: usize) -> usize {
+        let thread = &self.threads[me];
+        let stores = self.stores(object);
+        if thread.fresh {
+            return stores.len() - 1;
+        }
+        let seen = thread.last_seen.get(&object).copied().unwrap_or(0);
+        stores
+            .iter()
+            .rposition(|store| store.seq <= seen || store.happens_before(&thread.clock))
+            .unwrap_or(0)
+    }
+
+    fn read(&mut self, me: usize, object: usize, index: usize, order: Ordering) -> u64 {
+        let store = &self.stores(object)[index];
+        let (seq, value, sync) = (store.seq, store.value, store.sync.clone());
+        let thread = &mut self.threads[me];
+        thread.last_seen.insert(object, seq);
+        if acquires(order) {
+            thread.clock.join(&sync);
+        } else {
+            thread.acquire_pending.join(&sync);
+        }
+        value
+    }
+
+    fn write(&mut self, me: usize, object: usize, value: u64, order: Ordering, rmw: bool) {
+        let thread = &mut self.threads[me];
+        let step = thread.clock.tick(me);
+        let mut sync = if releases(order) {
+            thread.clock.clone()
+        } else {
+            thread.release_fence.clone()
+        };
+        thread.fresh = false;
+        let Object::Atomic(stores) = &mut self.objects[object] else {
+            unreachable!("an UnsafeCell is not an atomic");
+        };
+        let last = stores.back().expect("an atomic has at least one store");
+        // A read-modify-write continues the release sequence of the store it
+        // replaces: whoever synchronized with that store also does with this one.
+        if rmw {
+            sync.join(&last.sync);
+        }
+        let seq = last.seq + 1;
+        stores.push_back(Store {
+            seq,
+            value,
+            by: Some((me, step)),
+            sync,
+        });
+        if stores.len() > HISTORY {
+            stores.pop_front();
+        }
+        self.threads[me].last_seen.insert(object, seq);
+        self.progress();
+    }
+
+    fn load(&mut self, me: usize, object: usize, order: Ordering) -> Result<u64, String> {
+        self.seq_cst_before(me, order);
+        let oldest = self.oldest_readable(me, object);
+        let newest = self.stores(object).len() - 1;
+        let index = newest - self.path.choose(newest - oldest + 1)?;
+        let value = self.read(me, object, index, order);
+        self.seq_cst_after(me, order);
+        self.log(me, || {
+            format!("load({order:?}) of atomic {object} = {value}")
+        });
+        Ok(value)
+    }
+
+    fn store(&mut self, me: usize, object: usize, value: u64, order: Ordering) {
+        self.seq_cst_before(me, order);
+        self.write(me, object, value, order, false);
+        self.seq_cst_after(me, order);
+        self.log(me, || {
+            format!("store({order:?}) of {value} to atomic {object}")
+        });
+    }
+
+    fn rmw(
+        &mut self,
+        me: usize,
+        object: usize,
+        order: Ordering,
+        f: impl FnOnce(u64) -> u64,
+    ) -> u64 {
+        self.seq_cst_before(me, order);
+        // A read-modify-write always reads the newest store.
+        let newest = self.stores(object).len() - 1;
+        let old = self.read(me, object, newest, order);
+        let new = f(old);
+        self.write(me, object, new, order, true);
+        self.seq_cst_after(me, order);
+        self.log(me, || {
+            format!("update({order:?}) of atomic {object} from {old} to {new}")
+        });
+        old
+    }
+
+    fn compare_exchange(
+        &mut self,
+        me: usize,
+        object: usize,
+        current: u64,
+        new: u64,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<Result<u64, u64>, String> {
+        self.seq_cst_before(me, success);
+        // Succeeding reads the newest store, like every read-modify-write, but
+        // failing is just a load: it may read any older store that holds
+        // another value than `current`.
+        let oldest = self.oldest_readable(me, object);
+        let stores = self.stores(object);
+        let newest = stores.len() - 1;
+        let succeeds = stores[newest].value == current;
+        let options: Vec<usize> = (oldest..=newest)
+            .rev()
+            .filter(|&i| i == newest || stores[i].value != current)
+            .collect();
+        let index = options[self.path.choose(options.len())?];
+        let result = if index == newest && succeeds {
+            self.read(me, object, newest, success);
+            self.write(me, object, new, success, true);
+            self.log(me, || {
+                format!("compare_exchange({success:?}) of atomic {object} from {current} to {new}")
+            });
+            Ok(current)
+        } else {
+            let actual = self.read(me, object, index, failure);
+            self.log(me, || {
+                format!("compare_exchange({failure:?}) of atomic {object} failed, it is {actual}")
+            });
+            Err(actual)
+        };
+        self.seq_cst_after(me, success);
+        Ok(result)
+    }
+
+    fn fence(&mut self, me: usize, order: Ordering) {
+        self.seq_cst_before(me, order);
+        let thread = &mut self.threads[me];
+        if acquires(order) {
+            let pending = mem::take(&mut thread.acquire_pending);
+            thread.clock.join(&pending);
+        }
+        if releases(order) {
+            thread.release_fence = thread.clock.clone();
+        }
+        self.seq_cst_after(me, order);
+        self.log(me, || format!("fence({order:?})"));
+    }
+
+    fn access(&mut self, me: usize, object: usize) -> Result<(), String> {
+        self.log(me, || format!("access to UnsafeCell {object}"));
+        let thread = &mut self.threads[me];
+        let Object::Cell(last) = &mut self.objects[object] else {
+            unreachable!("an atomic is not an UnsafeCell");
+        };
+        if let Some(Access {
+            thread: other,
+            step,
+        }) = *last
+            && other != me
+            && thread.clock.get(other) < step
+        {
+            return Err(format!(
+                "data race: thread {me} accessed UnsafeCell {object} while thread {other} \
+                 may still be using it, nothing orders the two accesses"
+            ));
+        }
+        let step = thread.clock.tick(me);
+        *last = Some(Access { thread: me, step });
+        Ok(())
+    }
+}
+
+/// The state of one run of the test, shared by all of its threads.
+struct Execution {
+    state: Mutex<State>,
+    /// Signalled whenever another thread may run, or the execution ends.
+    turn: Condvar,
+    /// The OS threads running the model threads, joined at the end.
+    threads: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+/// The panic payload that stops the threads of a failed execution.
+struct Stopped;
+
+impl Execution {
+    /// Locks the state. Only one thread runs at a time, and a panic while the
+    /// state is locked stops the execution anyway, so poisoning is ignored.
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Starts the OS thread for model thread `me`, which waits for its turn.
+    fn start(self: &Arc<Self>, me: usize, body: impl FnOnce() + Send + 'static) {
+        let execution = Arc::clone(self);
+        let thread = thread::Builder::new()
+            .name(format!("model thread {me}"))
+            .spawn(move || {
+                let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                    drop(execution.wait_turn(execution.lock(), me));
+                    CONTEXT.with(|context| {
+                        *context.borrow_mut() = Some((Arc::clone(&execution), me));
+                    });
+                    body();
+                }));
+                CONTEXT.with(|context| context.borrow_mut().take());
+                execution.finish(me, outcome);
+            })
+            .expect("failed to spawn a model thread");
+        self.threads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(thread);
+    }
+
+    /// A scheduling point: lets the scheduler pick who runs next, and returns
+    /// once it is our turn again. Returns `None` if the execution was stopped
+    /// while we are unwinding.
+    fn schedule(&self, me: usize, yielding: bool) -> Option<MutexGuard<'_, State>> {
+        let mut state = self.lock();
+        if state.failure.is_some() {
+            return self.stopped(state);
+        }
+        if yielding {
+            let thread = &mut state.threads[me];
+            thread.yielded = true;
+            thread.fresh = true;
+        }
+        self.switch(state, me)
+    }
+
+    /// Hands over to the thread the scheduler picks, which may be us again.
+    fn switch<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, State>,
+        me: usize,
+    ) -> Option<MutexGuard<'a, State>> {
+        match state.pick(me) {
+            Ok(Some(next)) => state.active = next,
+            Ok(None) => unreachable!("a running thread has not finished"),
+            Err(message) => return self.fail(state, message),
+        }
+        if state.active != me {
+            self.turn.notify_all();
+        }
+        self.wait_turn(state, me)
+    }
+
+    fn wait_turn<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, State>,
+        me: usize,
+    ) -> Option<MutexGuard<'a, State>> {
+        while state.active != me && state.failure.is_none() {
+            state = self
+                .turn
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        if state.failure.is_some() {
+            return self.stopped(state);
+        }
+        Some(state)
+    }
+
+    /// Stops the execution with a failure.
+    fn fail<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, State>,
+        message: String,
+    ) -> Option<MutexGuard<'a, State>> {
+        state.failure.get_or_insert(message);
+        self.turn.notify_all();
+        self.stopped(state)
+    }
+
+    /// Unwinds the current thread, now that the execution has stopped. A thread
+    /// that is already unwinding cannot do that again; its remaining steps
+    /// fall back to plain `std` behaviour instead.
+    fn stopped<'a>(&'a self, state: MutexGuard<'a, State>) -> Option<MutexGuard<'a, State>> {
+        drop(state);
+        if !thread::panicking() {
+            panic::resume_unwind(Box::new(Stopped));
+        }
+        None
+    }
+
+    fn finish(&self, me: usize, outcome: thread::Result<()>) {
+        let mut state = self.lock();
+        if let Err(payload) = outcome
+            && !payload.is::<Stopped>()
+        {
+            let message = format!("thread {me} panicked: {}", panic_message(&*payload));
+            state.failure.get_or_insert(message);
+        }
+        if state.failure.is_some() {
+            self.turn.notify_all();
+            return;
+        }
+
+        state.threads[me].status = Status::Finished;
+        state.log(me, || "finished".to_string());
+        for thread in &mut state.threads {
+            if thread.status == Status::Joining(me) {
+                thread.status = Status::Runnable;
+            }
+        }
+        state.progress();
+        match state.pick(me) {
+            Ok(Some(next)) => state.active = next,
+            Ok(None) => state.done = true,
+            Err(message) => state.failure = Some(message),
+        }
+        self.turn.notify_all();
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// Runs `op` as a step of the current model thread, after a scheduling point.
+/// Returns `None` outside a model check, or when the execution has stopped.
+fn step<R>(op: impl FnOnce(&mut State, usize) -> Result<R, String>) -> Option<R> {
+    let (execution, me) = context()?;
+    let mut state = execution.schedule(me, false)?;
+    match op(&mut state, me) {
+        Ok(result) => Some(result),
+        Err(message) => {
+            execution.fail(state, message);
+            None
+        }
+    }
+}
+
+/// Spawns a model thread, like `std::thread::spawn`.
+///
+/// # Panics
+///
+/// Panics if it is called outside a model check, or if the test already has
+/// 36 threads.
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (execution, me) = context().expect(OUTSIDE);
+    let mut state = execution.schedule(me, false).expect(OUTSIDE);
+    let thread = state.threads.len();
+    assert!(
+        thread < MAX_THREADS,
+        "a model check supports at most {MAX_THREADS} threads"
+    );
+    // Everything the parent did so far happens before the child starts.
+    let clock = state.threads[me].clock.clone();
+    state.threads[me].clock.tick(me);
+    state.threads.push(ThreadState::new(clock));
+    state.log(me, || format!("spawn thread {thread}"));
+    drop(state);
+
+    let result = Arc::new(Mutex::new(None));
+    execution.start(thread, {
+        let result = Arc::clone(&result);
+        move || {
+            let value = f();
+            *result.lock().unwrap_or_else(PoisonError::into_inner) = Some(value);
+        }
+    });
+    JoinHandle { thread, result }
+}
+
+/// The handle of a thread started with `spawn`.
+pub struct JoinHandle<T> {
+    thread: usize,
+    result: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Waits for the thread to finish and returns its result. A panic fails the
+    /// whole execution, so unlike `std`, this never returns `Err` for one.
+    pub fn join(self) -> thread::Result<T> {
+        let (execution, me) = context().expect(OUTSIDE);
+        let Some(mut state) = execution.schedule(me, false) else {
+            return Err(Box::new(Stopped));
+        };
+        if state.threads[self.thread].status != Status::Finished {
+            state.threads[me].status = Status::Joining(self.thread);
+            state = match execution.switch(state, me) {
+                Some(state) => state,
+                None => return Err(Box::new(Stopped)),
+            };
+        }
+        // Everything the thread did happens before `join` returns.
+        let clock = state.threads[self.thread].clock.clone();
+        state.threads[me].clock.join(&clock);
+        state.log(me, || format!("joined thread {}", self.thread));
+        drop(state);
+        let value = self
+            .result
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        Ok(value.expect("a finished thread has stored its result"))
+    }
+}
+
+/// A handle to a thread, to `unpark` it: a model thread inside a model check,
+/// and a `std::thread::Thread` outside.
+#[derive(Clone, Debug)]
+pub struct Thread(ThreadKind);
+
+#[derive(Clone, Debug)]
+enum ThreadKind {
+    Std(thread::Thread),
+    Model(usize),
+}
+
+impl Thread {
+    /// Wakes the thread up if it is parked, or makes its next `park` return at
+    /// once. Like in `std`, the unpark happens before the park returns.
+    pub fn unpark(&self) {
+        match &self.0 {
+            ThreadKind::Std(thread) => thread.unpark(),
+            ThreadKind::Model(target) => {
+                step(|state, me| {
+                    let clock = state.threads[me].clock.clone();
+                    let thread = &mut state.threads[*target];
+                    thread
+                        .unpark
+                        .get_or_insert_with(VClock::default)
+                        .join(&clock);
+                    if thread.status == Status::Parked {
+                        thread.status = Status::Runnable;
+                    }
+                    state.progress();
+                    state.log(me, || format!("unpark thread {target}"));
+                    Ok(())
+                });
+            }
+        }
+    }
+}
+
+/// Returns a handle to the current thread, like `std::thread::current`.
+pub fn current() -> Thread {
+    match context() {
+        Some((_, me)) => Thread(ThreadKind::Model(me)),
+        None => Thread(ThreadKind::Std(thread::current())),
+    }
+}
+
+/// Blocks until the thread is unparked, like `std::thread::park`.
+pub fn park() {
+    let Some((execution, me)) = context() else {
+        return thread::park();
+    };
+    let Some(mut state) = execution.schedule(me, false) else {
+        return;
+    };
+    if state.threads[me].unpark.is_none() {
+        state.threads[me].status = Status::Parked;
+        state.log(me, || "park".to_string());
+        state = match execution.switch(state, me) {
+            Some(state) => state,
+            None => return,
+        };
+    }
+    take_unpark(&mut state, me);
+}
+
+/// Like `std::thread::park_timeout`. In a model check, the timeout always
+/// runs out at once, so this only yields, unless an unpark is pending.
+pub fn park_timeout(timeout: Duration) {
+    let Some((execution, me)) = context() else {
+        return thread::park_timeout(timeout);
+    };
+    if let Some(mut state) = execution.schedule(me, true) {
+        take_unpark(&mut state, me);
+    }
+}
+
+fn take_unpark(state: &mut State, me: usize) {
+    let thread = &mut state.threads[me];
+    if let Some(clock) = thread.unpark.take() {
+        thread.clock.join(&clock);
+    }
+}
+
+/// Lets the other threads run first, like `std::thread::yield_now`. In a model
+/// check, the thread also reads the newest values afterwards.
+pub fn yield_now() {
+    match context() {
+        Some((execution, me)) => {
+            if let Some(mut state) = execution.schedule(me, true) {
+                state.log(me, || "yield".to_string());
+            }
+        }
+        None => thread::yield_now(),
+    }
+}
+
+/// Like `std::hint::spin_loop`. In a model check, spinning threads yield, so
+/// that the thread they wait for gets to run.
+pub fn spin_loop() {
+    if context().is_some() {
+        yield_now();
+    } else {
+        hint::spin_loop();
+    }
+}
+
+/// A memory fence, like `std::sync::atomic::fence`.
+///
+/// # Panics
+///
+/// Panics if `order` is `Relaxed`, like `std` does.
+pub fn fence(order: Ordering) {
+    assert_ne!(
+        order,
+        Ordering::Relaxed,
+        "there is no such thing as a relaxed fence"
+    );
+    step(|state, me| {
+        state.fence(me, order);
+        Ok(())
+    })
+    .unwrap_or_else(|| atomic::fence(order));
+}
+
+/// The value behind every model atomic. `value` holds the newest store, which
+/// is all that counts outside a model check.
+struct RawAtomic {
+    value: AtomicU64,
+    /// The execution that last used this atomic, and its number in it.
+    tag: AtomicU64,
+}
+
+/// The ordering of the load part of a fallback read-modify-write.
+fn load_order(order: Ordering) -> Ordering {
+    match order {
+        Ordering::Release => Ordering::Relaxed,
+        Ordering::AcqRel => Ordering::Acquire,
+        order => order,
+    }
+}
+
+impl RawAtomic {
+    const fn new(value: u64) -> Self {
+        Self {
+            value: AtomicU64::new(value),
+            tag: AtomicU64::new(0),
+        }
+    }
+
+    fn load(&self, order: Ordering) -> u64 {
+        step(|state, me| {
+            let object = state.atomic(&self.tag, &self.value);
+            state.load(me, object, order)
+        })
+        .unwrap_or_else(|| self.value.load(order))
+    }
+
+    fn store(&self, value: u64, order: Ordering) {
+        step(|state, me| {
+            let object = state.atomic(&self.tag, &self.value);
+            state.store(me, object, value, order);
+            self.value.store(value, Ordering::Relaxed);
+            Ok(())
+        })
+        .unwrap_or_else(|| self.value.store(value, order));
+    }
+
+    fn update(&self, order: Ordering, f: impl Fn(u64) -> u64) -> u64 {
+        step(|state, me| {
+            let object = state.atomic(&self.tag, &self.value);
+            let old = state.rmw(me, object, order, &f);
+            self.value.store(f(old), Ordering::Relaxed);
+            Ok(old)
+        })
+        .unwrap_or_else(|| {
+            match self
+                .value
+                .fetch_update(order, load_order(order), |v| Some(f(v)))
+            {
+                Ok(old) | Err(old) => old,
+            }
+        })
+    }
+
+    fn compare_exchange(
+        &self,
+        current: u64,
+        new: u64,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<u64, u64> {
+        step(|state, me| {
+            let object = state.atomic(&self.tag, &self.value);
+            let result = state.compare_exchange(me, object, current, new, success, failure)?;
+            if result.is_ok() {
+                self.value.store(new, Ordering::Relaxed);
+            }
+            Ok(result)
+        })
+        .unwrap_or_else(|| self.value.compare_exchange(current, new, success, failure))
+    }
+}
+
+/// An `AtomicBool` that model checks can see, see `Checker`.
+pub struct AtomicBool {
+    raw: RawAtomic,
+}
+
+impl AtomicBool {
+    pub const fn new(value: bool) -> Self {
+        Self {
+            raw: RawAtomic::new(value as u64),
+        }
+    }
+
+    pub fn load(&self, order: Ordering) -> bool {
+        self.raw.load(order) != 0
+    }
+
+    pub fn store(&self, value: bool, order: Ordering) {
+        self.raw.store(value as u64, order);
+    }
+
+    pub fn swap(&self, value: bool, order: Ordering) -> bool {
+        self.raw.update(order, |_| value as u64) != 0
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: bool,
+        new: bool,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<bool, bool> {
+        self.raw
+            .compare_exchange(current as u64, new as u64, success, failure)
+            .map(|v| v != 0)
+            .map_err(|v| v != 0)
+    }
+
+    /// Never fails spuriously in a model check.
+    pub fn compare_exchange_weak(
+        &self,
+        current: bool,
+        new: bool,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<bool, bool> {
+        self.compare_exchange(current, new, success, failure)
+    }
+}
+
+/// An `AtomicUsize` that model checks can see, see `Checker`.
+pub struct AtomicUsize {
+    raw: RawAtomic,
+}
+
+impl AtomicUsize {
+    pub const fn new(value: usize) -> Self {
+        Self {
+            raw: RawAtomic::new(value as u64),
+        }
+    }
+
+    pub fn load(&self, order: Ordering) -> usize {
+        self.raw.load(order) as usize
+    }
+
+    pub fn store(&self, value: usize, order: Ordering) {
+        self.raw.store(value as u64, order);
+    }
+
+    pub fn swap(&self, value: usize, order: Ordering) -> usize {
+        self.raw.update(order, |_| value as u64) as usize
+    }
+
+    pub fn fetch_add(&self, value: usize, order: Ordering) -> usize {
+        self.raw
+            .update(order, |v| (v as usize).wrapping_add(value) as u64) as usize
+    }
+
+    pub fn fetch_sub(&self, value: usize, order: Ordering) -> usize {
+        self.raw
+            .update(order, |v| (v as usize).wrapping_sub(value) as u64) as usize
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: usize,
+        new: usize,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<usize, usize> {
+        self.raw
+            .compare_exchange(current as u64, new as u64, success, failure)
+            .map(|v| v as usize)
+            .map_err(|v| v as usize)
+    }
+
+    /// Never fails spuriously in a model check.
+    pub fn compare_exchange_weak(
+        &self,
+        current: usize,
+        new: usize,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<usize, usize> {
+        self.compare_exchange(current, new, success, failure)
+    }
+}
+
+/// An `AtomicPtr` that model checks can see, see `Checker`.
+pub struct AtomicPtr<T> {
+    raw: RawAtomic,
+    marker: PhantomData<*mut T>,
+}
+
+// Safety: Like `std::sync::atomic::AtomicPtr`, it only hands out raw pointers.
+unsafe impl<T> Send for AtomicPtr<T> {}
+unsafe impl<T> Sync for AtomicPtr<T> {}
+
+fn from_ptr<T>(ptr: *mut T) -> u64 {
+    ptr.expose_provenance() as u64
+}
+
+fn to_ptr<T>(value: u64) -> *mut T {
+    ptr::with_exposed_provenance_mut(value as usize)
+}
+
+impl<T> AtomicPtr<T> {
+    pub fn new(ptr: *mut T) -> Self {
+        Self {
+            raw: RawAtomic::new(from_ptr(ptr)),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn load(&self, order: Ordering) -> *mut T {
+        to_ptr(self.raw.load(order))
+    }
+
+    pub fn store(&self, ptr: *mut T, order: Ordering) {
+        self.raw.store(from_ptr(ptr), order);
+    }
+
+    pub fn swap(&self, ptr: *mut T, order: Ordering) -> *mut T {
+        let value = from_ptr(ptr);
+        to_ptr(self.raw.update(order, |_| value))
+    }
+
+    pub fn compare_exchange(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T> {
+        self.raw
+            .compare_exchange(from_ptr(current), from_ptr(new), success, failure)
+            .map(to_ptr)
+            .map_err(to_ptr)
+    }
+
+    /// Never fails spuriously in a model check.
+    pub fn compare_exchange_weak(
+        &self,
+        current: *mut T,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<*mut T, *mut T> {
+        self.compare_exchange(current, new, success, failure)
+    }
+}
+
+/// An `UnsafeCell` that catches data races in model checks.
+///
+/// Every `get` counts as an access that may write, so two threads calling it
+/// must be ordered by some synchronization, or the execution fails. That is
+/// exactly what a lock has to guarantee for the data it protects.
+pub struct UnsafeCell<T> {
+    value: std::cell::UnsafeCell<T>,
+    tag: AtomicU64,
+}
+
+impl<T> UnsafeCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: std::cell::UnsafeCell::new(value),
+            tag: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self) -> *mut T {
+        step(|state, me| {
+            let object = state.object(&self.tag, || Object::Cell(None));
+            state.access(me, object)
+        });
+        self.value.get()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
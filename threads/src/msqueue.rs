@@ -0,0 +1,150 @@
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::hazard::{self, HazardPointer};
+
+struct Node<T> {
+    /// Uninitialized in the sentinel node at the head of the queue.
+    value: MaybeUninit<T>,
+    next: AtomicPtr<Node<T>>,
+}
+
+impl<T> Node<T> {
+    fn new(value: MaybeUninit<T>) -> *mut Self {
+        Box::into_raw(Box::new(Node {
+            value,
+            next: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+/// A lock-free FIFO queue (Michael and Scott).
+///
+/// The queue is a linked list that always starts with a sentinel node. `push`
+/// appends behind `tail`, `pop` moves `head` one node forward; the node it moves
+/// to becomes the new sentinel, after its value has been taken out. Both ends
+/// are updated with compare-and-swap only, and a thread that finds `tail`
+/// lagging behind helps to move it forward instead of waiting.
+///
+/// Nodes that have been popped are freed through hazard pointers, so a thread
+/// that is still looking at one never reads freed memory.
+pub struct MsQueue<T> {
+    head: AtomicPtr<Node<T>>,
+    tail: AtomicPtr<Node<T>>,
+}
+
+unsafe impl<T: Send> Send for MsQueue<T> {}
+unsafe impl<T: Send> Sync for MsQueue<T> {}
+
+impl<T: Send> MsQueue<T> {
+    /// Creates an empty queue.
+    pub fn new() -> Self {
+        let sentinel = Node::new(MaybeUninit::uninit());
+        Self {
+            head: AtomicPtr::new(sentinel),
+            tail: AtomicPtr::new(sentinel),
+        }
+    }
+
+    /// Adds an item to the back of the queue.
+    pub fn push(&self, value: T) {
+        let node = Node::new(MaybeUninit::new(value));
+        let hazard = HazardPointer::new();
+        loop {
+            let tail = hazard.protect(&self.tail);
+            // Safety: `tail` is protected, so it has not been freed.
+            let next = unsafe { (*tail).next.load(Ordering::Acquire) };
+            if !next.is_null() {
+                // Another push has linked its node but not moved `tail` yet.
+                self.swing_tail(tail, next);
+                continue;
+            }
+            // `Release` publishes the node's value to the thread that pops it.
+            // Safety: As above.
+            if unsafe { &(*tail).next }
+                .compare_exchange(next, node, Ordering::Release, Ordering::Relaxed)
+                .is_ok()
+            {
+                self.swing_tail(tail, node);
+                return;
+            }
+        }
+    }
+
+    /// Removes the item at the front of the queue, if there is one.
+    pub fn pop(&self) -> Option<T> {
+        let head_hazard = HazardPointer::new();
+        let next_hazard = HazardPointer::new();
+        loop {
+            let head = head_hazard.protect(&self.head);
+            // Safety: `head` is protected, so it has not been freed.
+            let next = next_hazard.protect(unsafe { &(*head).next });
+            // `next` is only safe to use if it was still reachable when we
+            // protected it, which is the case if `head` has not moved on since.
+            if self.head.load(Ordering::Acquire) != head {
+                continue;
+            }
+            if next.is_null() {
+                return None;
+            }
+            let tail = self.tail.load(Ordering::Acquire);
+            if head == tail {
+                // `tail` lags behind: help it forward before popping past it.
+                self.swing_tail(tail, next);
+                continue;
+            }
+            if self
+                .head
+                .compare_exchange(head, next, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                // `next` is the new sentinel. We won the race for it, so we are
+                // the only thread that takes its value out.
+                // Safety: `next` is protected, and its value was initialized by
+                // the push that linked it.
+                let value = unsafe { (*next).value.assume_init_read() };
+                head_hazard.reset();
+                // Safety: The old sentinel is unreachable now.
+                unsafe { hazard::retire(head) };
+                return Some(value);
+            }
+        }
+    }
+
+    /// Moves `tail` from `from` to `to`, unless another thread already did.
+    fn swing_tail(&self, from: *mut Node<T>, to: *mut Node<T>) {
+        let _ = self
+            .tail
+            .compare_exchange(from, to, Ordering::Release, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the queue was empty at the time of the call.
+    pub fn is_empty(&self) -> bool {
+        let hazard = HazardPointer::new();
+        let head = hazard.protect(&self.head);
+        // Safety: `head` is protected, so it has not been freed.
+        unsafe { (*head).next.load(Ordering::Acquire).is_null() }
+    }
+}
+
+impl<T: Send> Default for MsQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drops the items that are still queued and frees all nodes.
+impl<T> Drop for MsQueue<T> {
+    fn drop(&mut self) {
+        let sentinel = *self.head.get_mut();
+        // Safety: We have exclusive access, and the sentinel holds no value.
+        let mut next = unsafe { Box::from_raw(sentinel) }.next.into_inner();
+        while !next.is_null() {
+            // Safety: Every node after the sentinel holds a value.
+            let mut node = unsafe { Box::from_raw(next) };
+            unsafe { node.value.assume_init_drop() };
+            next = node.next.into_inner();
+        }
+    }
+}
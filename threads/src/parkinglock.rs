@@ -0,0 +1,196 @@
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::hint;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, LockResult, PoisonError, TryLockError, TryLockResult};
+
+use crate::backoff::SpinThenYield;
+use crate::lock::Lock;
+use crate::lockdep::{self, LockId, Tracked};
+use crate::spinlock::{SpinLock, SpinLockGuard};
+use crate::waiter::Waiter;
+
+/// How often `lock()` retries before putting the thread to sleep.
+const SPIN_LIMIT: u32 = 100;
+
+/// Nobody holds the lock.
+const UNLOCKED: u8 = 0;
+/// The lock is held and no thread is (known to be) asleep waiting for it.
+const LOCKED: u8 = 1;
+/// The lock is held and there may be parked threads in the wait queue.
+const CONTENDED: u8 = 2;
+
+/// A hybrid lock that spins for a short while and then goes to sleep.
+///
+/// Short waits are handled like a `SpinLock`. Once a thread has spun `SPIN_LIMIT`
+/// times it joins a wait queue and calls `thread::park()`, so it stops using the
+/// CPU altogether. Releasing the lock unparks exactly one waiter. This keeps the
+/// lock usable when there are many more threads than cores, where a pure
+/// spinlock wastes whole time slices spinning on a holder that is not running.
+pub struct ParkingLock<T> {
+    state: AtomicU8,
+    /// The queue is only touched on the slow path and only for a few instructions,
+    /// so a small spinlock is good enough to protect it.
+    waiters: SpinLock<VecDeque<Arc<Waiter>>, SpinThenYield>,
+    /// The lock's node in the lock-order graph of `lockdep`.
+    dep: LockId,
+    value: UnsafeCell<T>,
+}
+
+/// A guard that provides access to the data locked by a `ParkingLock`.
+/// When the guard is dropped, the lock is released and one waiter is woken up.
+pub struct ParkingLockGuard<'a, T> {
+    lock: &'a ParkingLock<T>,
+    /// Opts out of the automatic `Send`/`Sync` impls, see below.
+    marker: PhantomData<*const ()>,
+}
+
+unsafe impl<T: Send> Send for ParkingLock<T> {}
+unsafe impl<T: Send> Sync for ParkingLock<T> {}
+// As for `SpinLockGuard`: `&guard` hands out `&T`, so sharing the guard between
+// threads requires `T: Sync`.
+unsafe impl<T: Sync> Sync for ParkingLockGuard<'_, T> {}
+
+impl<T> ParkingLock<T> {
+    /// Creates a new `ParkingLock` protecting the given data.
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU8::new(UNLOCKED),
+            waiters: SpinLock::with_backoff(VecDeque::new()),
+            dep: LockId::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires the lock, first spinning and then parking until it is available.
+    pub fn lock(&self) -> ParkingLockGuard<'_, T> {
+        lockdep::will_lock(self);
+        // Spin phase: behave like a spinlock for a bounded number of iterations.
+        for _ in 0..SPIN_LIMIT {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            hint::spin_loop();
+        }
+
+        // Park phase.
+        loop {
+            let waiter = Waiter::new();
+
+            {
+                let mut waiters = self.waiters();
+                // Marking the lock as `CONTENDED` and joining the queue happen while
+                // we hold the queue lock. An unlocking thread that sees `CONTENDED`
+                // takes the queue lock too, so it cannot miss us and leave us asleep.
+                if self.state.swap(CONTENDED, Ordering::Acquire) == UNLOCKED {
+                    // The lock was released in the meantime and is ours now. We leave
+                    // the state at `CONTENDED`: other threads may still be queued.
+                    lockdep::locked(self);
+                    return ParkingLockGuard {
+                        lock: self,
+                        marker: PhantomData,
+                    };
+                }
+                waiters.push_back(Arc::clone(&waiter));
+            }
+
+            waiter.wait();
+            // We were woken because the lock was released, but it is not handed to
+            // us directly: another thread may take it first, in which case we
+            // simply queue up again.
+        }
+    }
+
+    /// Attempts to acquire the lock without spinning or parking.
+    pub fn try_lock(&self) -> Option<ParkingLockGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        lockdep::locked(self);
+        Some(ParkingLockGuard {
+            lock: self,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns a mutable reference to the protected data.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Consumes the lock and returns the protected data.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Locks the wait queue. Pushing and popping cannot leave the queue in a
+    /// broken state, so there is nothing to worry about if it was poisoned.
+    fn waiters(&self) -> SpinLockGuard<'_, VecDeque<Arc<Waiter>>, SpinThenYield> {
+        self.waiters.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Unparks the waiter at the front of the queue, if there is one.
+    fn wake_one(&self) {
+        let waiter = self.waiters().pop_front();
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+    }
+}
+
+impl<T> Tracked for ParkingLock<T> {
+    fn lock_id(&self) -> &LockId {
+        &self.dep
+    }
+
+    fn kind(&self) -> &'static str {
+        "ParkingLock"
+    }
+}
+
+impl<T> Lock<T> for ParkingLock<T> {
+    type Guard<'a>
+        = ParkingLockGuard<'a, T>
+    where
+        Self: 'a;
+
+    fn new(value: T) -> Self {
+        ParkingLock::new(value)
+    }
+
+    fn lock(&self) -> LockResult<Self::Guard<'_>> {
+        Ok(ParkingLock::lock(self))
+    }
+
+    fn try_lock(&self) -> TryLockResult<Self::Guard<'_>> {
+        ParkingLock::try_lock(self).ok_or(TryLockError::WouldBlock)
+    }
+}
+
+impl<T> Drop for ParkingLockGuard<'_, T> {
+    fn drop(&mut self) {
+        lockdep::unlocked(self.lock);
+        // `Release` publishes our writes to the next holder. Only if somebody may
+        // be asleep do we pay for touching the wait queue.
+        if self.lock.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            self.lock.wake_one();
+        }
+    }
+}
+
+impl<T> Deref for ParkingLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: The existence of the guard proves we have exclusive access.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for ParkingLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The existence of the guard proves we have exclusive access.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
@@ -0,0 +1,152 @@
+use std::cell::Cell;
+use std::hint;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::lockdep::{self, LockId, Tracked};
+use crate::threadid;
+
+/// Means that no thread holds the lock.
+const NO_OWNER: u64 = 0;
+
+/// A spinlock that the thread holding it may lock again.
+///
+/// With a plain `SpinLock`, a thread that calls `lock()` while it already holds
+/// the lock spins forever, waiting for itself. This lock remembers which thread
+/// owns it and how often that thread has locked it. The owner can lock it again
+/// right away; the lock is released when the last of its guards is dropped.
+///
+/// Because the same thread may hold several guards at once, the guards only
+/// give out shared references. Combine it with a `Cell` or `RefCell` to mutate.
+pub struct ReentrantSpinLock<T> {
+    /// The identity of the owning thread, or `NO_OWNER`. Taking the lock means
+    /// swapping our own identity in here.
+    owner: AtomicU64,
+    /// How many guards the owner currently holds. Only the owner touches it.
+    count: Cell<usize>,
+    /// The lock's node in the lock-order graph of `lockdep`. Locking it again
+    /// while holding it is fine, so only the first `lock` of the owner counts.
+    dep: LockId,
+    value: T,
+}
+
+/// A guard that provides shared access to the data of a `ReentrantSpinLock`.
+pub struct ReentrantSpinLockGuard<'a, T> {
+    lock: &'a ReentrantSpinLock<T>,
+    /// The guard has to be dropped by the thread that owns the lock, so it must
+    /// not be sent to another thread. The raw pointer makes it `!Send`.
+    marker: PhantomData<*const ()>,
+}
+
+// Only the owning thread ever gets to see the data or `count`, and ownership
+// moves between threads with `Acquire`/`Release` synchronisation.
+unsafe impl<T: Send> Send for ReentrantSpinLock<T> {}
+unsafe impl<T: Send> Sync for ReentrantSpinLock<T> {}
+unsafe impl<T: Sync> Sync for ReentrantSpinLockGuard<'_, T> {}
+
+impl<T> ReentrantSpinLock<T> {
+    /// Creates a new `ReentrantSpinLock` protecting the given data.
+    pub const fn new(value: T) -> Self {
+        Self {
+            owner: AtomicU64::new(NO_OWNER),
+            count: Cell::new(0),
+            dep: LockId::new(),
+            value,
+        }
+    }
+
+    /// Acquires the lock, spinning until it is available. Returns immediately
+    /// if the current thread already holds the lock.
+    pub fn lock(&self) -> ReentrantSpinLockGuard<'_, T> {
+        let me = threadid::current();
+        if !self.lock_again(me) {
+            lockdep::will_lock(self);
+            while self
+                .owner
+                .compare_exchange_weak(NO_OWNER, me, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                hint::spin_loop();
+            }
+            self.count.set(1);
+            lockdep::locked(self);
+        }
+        ReentrantSpinLockGuard {
+            lock: self,
+            marker: PhantomData,
+        }
+    }
+
+    /// Attempts to acquire the lock without spinning. Always succeeds if the
+    /// current thread already holds the lock.
+    pub fn try_lock(&self) -> Option<ReentrantSpinLockGuard<'_, T>> {
+        let me = threadid::current();
+        if !self.lock_again(me) {
+            self.owner
+                .compare_exchange(NO_OWNER, me, Ordering::Acquire, Ordering::Relaxed)
+                .ok()?;
+            self.count.set(1);
+            lockdep::locked(self);
+        }
+        Some(ReentrantSpinLockGuard {
+            lock: self,
+            marker: PhantomData,
+        })
+    }
+
+    /// Bumps the recursion count if `me` already owns the lock.
+    fn lock_again(&self, me: u64) -> bool {
+        // `Relaxed` is enough: the only thread that can have stored `me` here is
+        // the current thread itself, so this load cannot race with a store of `me`.
+        if self.owner.load(Ordering::Relaxed) != me {
+            return false;
+        }
+        let count = self
+            .count
+            .get()
+            .checked_add(1)
+            .expect("lock count overflow");
+        self.count.set(count);
+        true
+    }
+
+    /// Returns a mutable reference to the protected data.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+
+    /// Consumes the lock and returns the protected data.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Tracked for ReentrantSpinLock<T> {
+    fn lock_id(&self) -> &LockId {
+        &self.dep
+    }
+
+    fn kind(&self) -> &'static str {
+        "ReentrantSpinLock"
+    }
+}
+
+impl<T> Drop for ReentrantSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        let count = self.lock.count.get() - 1;
+        self.lock.count.set(count);
+        // Only the last guard of the owner releases the lock.
+        if count == 0 {
+            lockdep::unlocked(self.lock);
+            self.lock.owner.store(NO_OWNER, Ordering::Release);
+        }
+    }
+}
+
+impl<T> Deref for ReentrantSpinLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        &self.lock.value
+    }
+}
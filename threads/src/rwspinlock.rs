@@ -0,0 +1,304 @@
+use std::cell::UnsafeCell;
+use std::hint;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::lockdep::{self, LockId, Tracked};
+
+/// Set while a writer holds the lock.
+const WRITER: usize = 1;
+/// Set while a thread holds an upgradable read guard.
+const UPGRADABLE: usize = 1 << 1;
+/// Set by waiting writers when the lock prefers writers. New readers back off.
+const WRITER_WAITING: usize = 1 << 2;
+/// Every reader adds this to the state, so the reader count lives in the upper bits.
+const READER: usize = 1 << 3;
+
+/// A reader-writer spinlock: many readers *or* one writer.
+///
+/// Besides plain read and write guards it hands out an *upgradable* read guard.
+/// It can be held alongside ordinary readers, but only by one thread at a time
+/// and never alongside a writer, so it can later be turned into a write guard
+/// without anybody else getting to write in between.
+///
+/// By default readers may always join other readers, which means a steady stream
+/// of readers can keep a writer waiting forever. A lock created with
+/// `with_writer_preference` stops admitting new readers as soon as a writer is
+/// waiting, so the writer gets in once the current readers are done.
+pub struct RwSpinLock<T> {
+    /// All the lock's bookkeeping in one word: the flags above plus the reader count.
+    state: AtomicUsize,
+    prefer_writers: bool,
+    /// The lock's node in the lock-order graph of `lockdep`. Read and write
+    /// locks count the same: a reader waiting behind a writer can deadlock too.
+    dep: LockId,
+    value: UnsafeCell<T>,
+}
+
+/// A guard that provides shared access to the data of an `RwSpinLock`.
+pub struct RwSpinLockReadGuard<'a, T> {
+    lock: &'a RwSpinLock<T>,
+}
+
+/// A guard that provides exclusive access to the data of an `RwSpinLock`.
+pub struct RwSpinLockWriteGuard<'a, T> {
+    lock: &'a RwSpinLock<T>,
+}
+
+/// A read guard that can be atomically promoted to a write guard with `upgrade`.
+pub struct RwSpinLockUpgradableGuard<'a, T> {
+    lock: &'a RwSpinLock<T>,
+}
+
+// Readers on different threads see the same `T` at the same time, so unlike
+// `SpinLock` we also need `T: Sync` for the lock to be shared.
+unsafe impl<T: Send> Send for RwSpinLock<T> {}
+unsafe impl<T: Send + Sync> Sync for RwSpinLock<T> {}
+
+impl<T> RwSpinLock<T> {
+    /// Creates a new `RwSpinLock` that lets readers join other readers at any time.
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            prefer_writers: false,
+            dep: LockId::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Creates a new `RwSpinLock` that holds back new readers while a writer waits.
+    pub const fn with_writer_preference(value: T) -> Self {
+        Self {
+            state: AtomicUsize::new(0),
+            prefer_writers: true,
+            dep: LockId::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns `true` if a reader arriving now has to wait.
+    fn readers_blocked(&self, state: usize) -> bool {
+        state & WRITER != 0 || (self.prefer_writers && state & WRITER_WAITING != 0)
+    }
+
+    /// Tells new readers to hold back, if this lock prefers writers.
+    fn announce_writer(&self) {
+        if self.prefer_writers {
+            self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+        }
+    }
+
+    /// Acquires shared read access, spinning while a writer holds (or, with
+    /// writer preference, waits for) the lock.
+    pub fn read(&self) -> RwSpinLockReadGuard<'_, T> {
+        lockdep::will_lock(self);
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Attempts to acquire shared read access without spinning.
+    pub fn try_read(&self) -> Option<RwSpinLockReadGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if self.readers_blocked(state) {
+                return None;
+            }
+            // `Acquire` pairs with the `Release` of the last writer to drop its guard.
+            match self.state.compare_exchange_weak(
+                state,
+                state + READER,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    lockdep::locked(self);
+                    return Some(RwSpinLockReadGuard { lock: self });
+                }
+                // Another reader came or went; try again with the fresh state.
+                Err(current) => state = current,
+            }
+        }
+    }
+
+    /// Acquires exclusive write access, spinning until all readers are gone.
+    pub fn write(&self) -> RwSpinLockWriteGuard<'_, T> {
+        lockdep::will_lock(self);
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            self.announce_writer();
+            hint::spin_loop();
+        }
+    }
+
+    /// Attempts to acquire exclusive write access without spinning.
+    pub fn try_write(&self) -> Option<RwSpinLockWriteGuard<'_, T>> {
+        let state = self.state.load(Ordering::Relaxed);
+        // Free means: no readers, no writer, no upgradable reader. A set
+        // `WRITER_WAITING` bit does not count, and taking the lock clears it. Other
+        // waiting writers will set it again on their next attempt.
+        if state & !WRITER_WAITING != 0 {
+            return None;
+        }
+        self.state
+            .compare_exchange(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        lockdep::locked(self);
+        Some(RwSpinLockWriteGuard { lock: self })
+    }
+
+    /// Acquires an upgradable read guard, spinning while a writer or another
+    /// upgradable reader holds the lock.
+    pub fn upgradable_read(&self) -> RwSpinLockUpgradableGuard<'_, T> {
+        lockdep::will_lock(self);
+        loop {
+            if let Some(guard) = self.try_upgradable_read() {
+                return guard;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Attempts to acquire an upgradable read guard without spinning.
+    pub fn try_upgradable_read(&self) -> Option<RwSpinLockUpgradableGuard<'_, T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        loop {
+            if self.readers_blocked(state) || state & UPGRADABLE != 0 {
+                return None;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state | UPGRADABLE,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    lockdep::locked(self);
+                    return Some(RwSpinLockUpgradableGuard { lock: self });
+                }
+                Err(current) => state = current,
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the protected data.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Consumes the lock and returns the protected data.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T> Tracked for RwSpinLock<T> {
+    fn lock_id(&self) -> &LockId {
+        &self.dep
+    }
+
+    fn kind(&self) -> &'static str {
+        "RwSpinLock"
+    }
+}
+
+impl<'a, T> RwSpinLockUpgradableGuard<'a, T> {
+    /// Promotes the guard to a write guard, spinning until the other readers are done.
+    ///
+    /// No writer can slip in between: while we hold the upgradable guard, no
+    /// writer (and no other upgradable reader) can acquire the lock, so the data
+    /// we saw while reading is still the data we are about to modify.
+    pub fn upgrade(self) -> RwSpinLockWriteGuard<'a, T> {
+        let mut guard = self;
+        loop {
+            match guard.try_upgrade() {
+                Ok(write_guard) => return write_guard,
+                Err(upgradable) => guard = upgradable,
+            }
+            // We are a waiting writer too: with writer preference, stop new readers
+            // from joining so the current ones can drain.
+            guard.lock.announce_writer();
+            hint::spin_loop();
+        }
+    }
+
+    /// Attempts to promote the guard to a write guard without spinning.
+    /// Gives the upgradable guard back if other readers still hold the lock.
+    pub fn try_upgrade(self) -> Result<RwSpinLockWriteGuard<'a, T>, Self> {
+        let lock = self.lock;
+        let state = lock.state.load(Ordering::Relaxed);
+        if state & !WRITER_WAITING != UPGRADABLE {
+            return Err(self);
+        }
+        match lock
+            .state
+            .compare_exchange(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => {
+                // The `UPGRADABLE` bit is already gone, so our `Drop` must not run.
+                // The lock stays held, so `lockdep` does not need to hear about it.
+                std::mem::forget(self);
+                Ok(RwSpinLockWriteGuard { lock })
+            }
+            Err(_) => Err(self),
+        }
+    }
+}
+
+impl<T> Drop for RwSpinLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        lockdep::unlocked(self.lock);
+        self.lock.state.fetch_sub(READER, Ordering::Release);
+    }
+}
+
+impl<T> Drop for RwSpinLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        lockdep::unlocked(self.lock);
+        // Only clear our own bit: a `WRITER_WAITING` set meanwhile must survive.
+        self.lock.state.fetch_and(!WRITER, Ordering::Release);
+    }
+}
+
+impl<T> Drop for RwSpinLockUpgradableGuard<'_, T> {
+    fn drop(&mut self) {
+        lockdep::unlocked(self.lock);
+        self.lock.state.fetch_and(!UPGRADABLE, Ordering::Release);
+    }
+}
+
+impl<T> Deref for RwSpinLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: While any read guard exists, no writer can hold the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Deref for RwSpinLockUpgradableGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: While an upgradable guard exists, no writer can hold the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> Deref for RwSpinLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: The write guard proves we have exclusive access.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for RwSpinLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The write guard proves we have exclusive access.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
@@ -0,0 +1,106 @@
+use std::mem;
+use std::sync::PoisonError;
+
+use crate::condvar::SpinCondvar;
+use crate::spinlock::{SpinLock, SpinLockGuard};
+
+/// A counting semaphore: hands out up to a fixed number of permits, for
+/// example to limit how many threads use a resource at the same time.
+///
+/// Permits are returned automatically when the `SemaphorePermit` that holds
+/// them is dropped. The semaphore is not fair: a thread that needs many
+/// permits may keep waiting while threads that need fewer get served.
+pub struct Semaphore {
+    permits: SpinLock<usize>,
+    available: SpinCondvar,
+}
+
+/// Permits acquired from a `Semaphore`. Dropping it gives them back.
+#[must_use = "the permits are released right away if the guard is not used"]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl Semaphore {
+    /// Creates a semaphore with the given number of permits.
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            permits: SpinLock::new(permits),
+            available: SpinCondvar::new(),
+        }
+    }
+
+    /// Locks the counter. Nothing can panic while it is locked, so poisoning is harmless.
+    fn lock(&self) -> SpinLockGuard<'_, usize> {
+        self.permits.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Acquires one permit, blocking until one is available.
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Acquires `n` permits at once, blocking until that many are available.
+    /// Waiting for them all at once, instead of one by one, cannot deadlock
+    /// with another thread that holds some of the permits and waits for more.
+    pub fn acquire_many(&self, n: usize) -> SemaphorePermit<'_> {
+        let mut permits = self
+            .available
+            .wait_while(self.lock(), |permits| *permits < n)
+            .unwrap_or_else(PoisonError::into_inner);
+        *permits -= n;
+        SemaphorePermit {
+            semaphore: self,
+            permits: n,
+        }
+    }
+
+    /// Acquires one permit if one is available right now.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        self.try_acquire_many(1)
+    }
+
+    /// Acquires `n` permits if that many are available right now.
+    pub fn try_acquire_many(&self, n: usize) -> Option<SemaphorePermit<'_>> {
+        let mut permits = self.lock();
+        if *permits < n {
+            return None;
+        }
+        *permits -= n;
+        Some(SemaphorePermit {
+            semaphore: self,
+            permits: n,
+        })
+    }
+
+    /// Returns the number of permits that are available right now.
+    pub fn available_permits(&self) -> usize {
+        *self.lock()
+    }
+
+    /// Adds `n` new permits to the semaphore.
+    pub fn add_permits(&self, n: usize) {
+        *self.lock() += n;
+        // Waiters may need different numbers of permits, so let them all check.
+        self.available.notify_all();
+    }
+}
+
+impl SemaphorePermit<'_> {
+    /// Returns how many permits this guard holds.
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Keeps the permits taken for good instead of returning them.
+    pub fn forget(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
@@ -0,0 +1,138 @@
+use std::cell::UnsafeCell;
+use std::hint;
+use std::ops::{Deref, DerefMut};
+use std::ptr;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+
+/// A sequence lock for small, `Copy` data that is read far more often than it
+/// is written.
+///
+/// Writers take the lock by making the sequence number odd and release it by
+/// making it even again. Readers never write to the lock at all: they note the
+/// sequence number, copy the data and check that the sequence number did not
+/// change in the meantime. If it did, a writer was busy and they simply retry.
+///
+/// Compared with an `RwLock`, where every reader has to update the reader count
+/// and thereby steals the lock's cache line from all other cores, readers scale
+/// perfectly. The price is that a reader may have to retry while writes are
+/// frequent, and that it always gets a *copy* of the data, hence `T: Copy`.
+pub struct SeqLock<T: Copy> {
+    /// Even while nobody is writing, odd while a writer holds the lock.
+    seq: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+/// A guard that provides write access to the data of a `SeqLock`.
+/// When the guard is dropped, the new value is published to the readers.
+pub struct SeqLockWriteGuard<'a, T: Copy> {
+    lock: &'a SeqLock<T>,
+    /// The (odd) sequence number we set when taking the lock.
+    seq: usize,
+}
+
+// Readers only ever get copies of the data, so sharing the lock between threads
+// just needs the data to be sendable.
+unsafe impl<T: Copy + Send> Send for SeqLock<T> {}
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    /// Creates a new `SeqLock` protecting the given data.
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicUsize::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a copy of the data, retrying while a writer is busy.
+    pub fn read(&self) -> T {
+        loop {
+            // `Acquire` pairs with the `Release` at the end of the last write, so
+            // we see everything that writer wrote.
+            let before = self.seq.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                // A write is in progress; the data is being changed right now.
+                hint::spin_loop();
+                continue;
+            }
+
+            // Safety: A writer may be changing the data while we copy it, so the
+            // copy may be torn. We never use a torn copy: the sequence check below
+            // detects the write and we throw the copy away. `read_volatile` keeps
+            // the compiler from assuming the data cannot change underneath us.
+            // (Strictly speaking the Rust memory model calls this racy read a data
+            // race; this is the accepted way to write a seqlock until Rust gets
+            // atomic memcpy.)
+            let value = unsafe { ptr::read_volatile(self.data.get()) };
+
+            // The fence keeps the copy above from being moved after the load below.
+            atomic::fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) == before {
+                return value;
+            }
+        }
+    }
+
+    /// Takes the write lock, spinning while another writer holds it.
+    /// Readers are never blocked; they retry until the guard is dropped.
+    pub fn write(&self) -> SeqLockWriteGuard<'_, T> {
+        loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+            if seq & 1 == 0
+                && self
+                    .seq
+                    .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                // Readers must not see any of our data writes without also seeing
+                // the odd sequence number, so no write may move above this point.
+                atomic::fence(Ordering::Release);
+                return SeqLockWriteGuard {
+                    lock: self,
+                    seq: seq + 1,
+                };
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Replaces the data with `value`.
+    pub fn store(&self, value: T) {
+        *self.write() = value;
+    }
+
+    /// Returns a mutable reference to the data.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+
+    /// Consumes the lock and returns the data.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: Copy> Drop for SeqLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        // Making the sequence number even again publishes the new data. Readers
+        // that started during the write see a different number and retry.
+        self.lock
+            .seq
+            .store(self.seq.wrapping_add(1), Ordering::Release);
+    }
+}
+
+impl<T: Copy> Deref for SeqLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: Only one writer at a time holds a guard, and readers never write.
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: Copy> DerefMut for SeqLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: Only one writer at a time holds a guard, and readers never write.
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
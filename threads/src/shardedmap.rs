@@ -0,0 +1,313 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::num::NonZeroUsize;
+use std::ops::{Deref, DerefMut};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+use std::vec;
+
+/// A hash map that many threads can use at the same time.
+///
+/// A single `RwLock<HashMap>` lets only one writer in at a time, and even
+/// readers all update the same reader count. `ShardedMap` splits the entries
+/// over several `RwLock<HashMap>` shards, picked by the hash of the key, so
+/// threads that work on different keys rarely touch the same lock.
+///
+/// Values are returned by clone, because a reference into a shard cannot
+/// outlive the shard's lock. Use `entry` to update a value in place.
+pub struct ShardedMap<K, V, S = RandomState> {
+    shards: Box<[RwLock<HashMap<K, V, S>>]>,
+    hasher: S,
+}
+
+impl<K: Eq + Hash, V> ShardedMap<K, V> {
+    /// Creates an empty map with four shards per core.
+    pub fn new() -> Self {
+        let cores = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        Self::with_shards(cores * 4)
+    }
+
+    /// Creates an empty map with the given number of shards. With a single
+    /// shard, the map behaves like one `RwLock<HashMap>`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is 0.
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+}
+
+impl<K: Eq + Hash, V> Default for ShardedMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher + Clone> ShardedMap<K, V, S> {
+    /// Creates an empty map with the given number of shards, which hashes keys
+    /// with `hasher`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `shards` is 0.
+    pub fn with_shards_and_hasher(shards: usize, hasher: S) -> Self {
+        assert!(shards > 0, "a sharded map needs at least one shard");
+        let shards = (0..shards)
+            .map(|_| RwLock::new(HashMap::with_hasher(hasher.clone())))
+            .collect();
+        Self { shards, hasher }
+    }
+
+    /// Returns the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    fn shard<Q>(&self, key: &Q) -> &RwLock<HashMap<K, V, S>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + ?Sized,
+    {
+        // The shard's own `HashMap` picks buckets with the low bits of the same
+        // hash. Picking the shard with the high bits keeps the keys of one shard
+        // from all landing in the same few buckets.
+        let hash = self.hasher.hash_one(key);
+        &self.shards[(hash >> 32) as usize % self.shards.len()]
+    }
+
+    /// Returns a copy of the value for `key`, if there is one.
+    pub fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        read(self.shard(key)).get(key).cloned()
+    }
+
+    /// Returns `true` if the map holds a value for `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        read(self.shard(key)).contains_key(key)
+    }
+
+    /// Inserts a value and returns the one it replaced, if any.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        write(self.shard(&key)).insert(key, value)
+    }
+
+    /// Removes the value for `key` and returns it, if there was one.
+    pub fn remove<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        write(self.shard(key)).remove(key)
+    }
+
+    /// Returns the entry for `key`, to inspect or update it in place.
+    ///
+    /// The entry holds the write lock of the key's shard until it is dropped,
+    /// so a read-modify-write through it cannot race with other threads. Other
+    /// keys in the same shard are blocked in the meantime, so do not keep it
+    /// around for long, and do not touch the map from the same thread while
+    /// holding it.
+    pub fn entry(&self, key: K) -> Entry<'_, K, V, S> {
+        let shard = write(self.shard(&key));
+        if shard.contains_key(&key) {
+            Entry::Occupied(OccupiedEntry { shard, key })
+        } else {
+            Entry::Vacant(VacantEntry { shard, key })
+        }
+    }
+
+    /// Returns the number of entries. Other threads may change it right away.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|shard| read(shard).len()).sum()
+    }
+
+    /// Returns `true` if the map holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| read(shard).is_empty())
+    }
+
+    /// Returns an iterator over copies of all entries.
+    ///
+    /// The shards are copied one at a time, each while holding its read lock
+    /// only briefly. Every shard is consistent in itself, but changes to other
+    /// shards made while iterating may or may not show up.
+    pub fn iter(&self) -> Iter<'_, K, V, S>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        Iter {
+            shards: self.shards.iter(),
+            current: Vec::new().into_iter(),
+        }
+    }
+}
+
+/// Locks a shard for reading. A panic while a shard was locked (in a `Hash` or
+/// `Eq` implementation, say) cannot leave the `HashMap` in an unsafe state, so
+/// poisoning is ignored.
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Locks a shard for writing, ignoring poisoning like `read` does.
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// An iterator over copies of the entries of a `ShardedMap`, see `ShardedMap::iter`.
+pub struct Iter<'a, K, V, S> {
+    shards: std::slice::Iter<'a, RwLock<HashMap<K, V, S>>>,
+    /// The copy of the shard we are in.
+    current: vec::IntoIter<(K, V)>,
+}
+
+impl<K: Clone, V: Clone, S> Iterator for Iter<'_, K, V, S> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            if let Some(entry) = self.current.next() {
+                return Some(entry);
+            }
+            let shard = read(self.shards.next()?);
+            self.current = shard
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect::<Vec<_>>()
+                .into_iter();
+        }
+    }
+}
+
+/// A view into one entry of a `ShardedMap`, see `ShardedMap::entry`.
+pub enum Entry<'a, K, V, S = RandomState> {
+    Occupied(OccupiedEntry<'a, K, V, S>),
+    Vacant(VacantEntry<'a, K, V, S>),
+}
+
+/// An entry that holds a value. It dereferences to that value.
+pub struct OccupiedEntry<'a, K, V, S = RandomState> {
+    shard: RwLockWriteGuard<'a, HashMap<K, V, S>>,
+    key: K,
+}
+
+/// An entry without a value.
+pub struct VacantEntry<'a, K, V, S = RandomState> {
+    shard: RwLockWriteGuard<'a, HashMap<K, V, S>>,
+    key: K,
+}
+
+impl<'a, K: Eq + Hash + Clone, V, S: BuildHasher> Entry<'a, K, V, S> {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        match self {
+            Entry::Occupied(entry) => entry.key(),
+            Entry::Vacant(entry) => entry.key(),
+        }
+    }
+
+    /// Inserts `default` if the entry is empty, and returns the occupied entry.
+    pub fn or_insert(self, default: V) -> OccupiedEntry<'a, K, V, S> {
+        self.or_insert_with(|| default)
+    }
+
+    /// Inserts the result of `default` if the entry is empty, and returns the
+    /// occupied entry.
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> OccupiedEntry<'a, K, V, S> {
+        match self {
+            Entry::Occupied(entry) => entry,
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// Inserts `V::default()` if the entry is empty, and returns the occupied entry.
+    pub fn or_default(self) -> OccupiedEntry<'a, K, V, S>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    /// Calls `f` on the value if there is one.
+    pub fn and_modify<F: FnOnce(&mut V)>(mut self, f: F) -> Self {
+        if let Entry::Occupied(entry) = &mut self {
+            f(entry.get_mut());
+        }
+        self
+    }
+}
+
+impl<'a, K: Eq + Hash, V, S: BuildHasher> OccupiedEntry<'a, K, V, S> {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Returns the value.
+    pub fn get(&self) -> &V {
+        self.shard
+            .get(&self.key)
+            .expect("an occupied entry keeps its shard locked")
+    }
+
+    /// Returns the value for changing it in place.
+    pub fn get_mut(&mut self) -> &mut V {
+        self.shard
+            .get_mut(&self.key)
+            .expect("an occupied entry keeps its shard locked")
+    }
+
+    /// Replaces the value and returns the old one.
+    pub fn insert(&mut self, value: V) -> V {
+        std::mem::replace(self.get_mut(), value)
+    }
+
+    /// Removes the entry from the map and returns its value.
+    pub fn remove(mut self) -> V {
+        self.shard
+            .remove(&self.key)
+            .expect("an occupied entry keeps its shard locked")
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> Deref for OccupiedEntry<'_, K, V, S> {
+    type Target = V;
+    fn deref(&self) -> &V {
+        self.get()
+    }
+}
+
+impl<K: Eq + Hash, V, S: BuildHasher> DerefMut for OccupiedEntry<'_, K, V, S> {
+    fn deref_mut(&mut self) -> &mut V {
+        self.get_mut()
+    }
+}
+
+impl<'a, K: Eq + Hash + Clone, V, S: BuildHasher> VacantEntry<'a, K, V, S> {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Inserts a value and returns the now occupied entry. The entry keeps a
+    /// copy of the key to find the value again.
+    pub fn insert(mut self, value: V) -> OccupiedEntry<'a, K, V, S> {
+        self.shard.insert(self.key.clone(), value);
+        OccupiedEntry {
+            shard: self.shard,
+            key: self.key,
+        }
+    }
+}
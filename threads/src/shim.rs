@@ -0,0 +1,31 @@
+// The primitives that `SpinLock`, the backoff strategies and `SpinCondvar` are
+// built on, under the same paths as in `std`. With the `model` cargo feature
+// they come from `model` instead, so that model checks see every step of them.
+
+pub(crate) mod atomic {
+    #[cfg(feature = "model")]
+    pub(crate) use crate::model::AtomicBool;
+    #[cfg(not(feature = "model"))]
+    pub(crate) use std::sync::atomic::AtomicBool;
+}
+
+pub(crate) mod cell {
+    #[cfg(feature = "model")]
+    pub(crate) use crate::model::UnsafeCell;
+    #[cfg(not(feature = "model"))]
+    pub(crate) use std::cell::UnsafeCell;
+}
+
+pub(crate) mod hint {
+    #[cfg(feature = "model")]
+    pub(crate) use crate::model::spin_loop;
+    #[cfg(not(feature = "model"))]
+    pub(crate) use std::hint::spin_loop;
+}
+
+pub(crate) mod thread {
+    #[cfg(feature = "model")]
+    pub(crate) use crate::model::{Thread, current, park, park_timeout, yield_now};
+    #[cfg(not(feature = "model"))]
+    pub(crate) use std::thread::{Thread, current, park, park_timeout, yield_now};
+}
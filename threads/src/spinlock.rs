@@ -1,42 +1,181 @@
-use std::cell::UnsafeCell;
-use std::hint;
+use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::ptr::NonNull;
+#[cfg(debug_assertions)]
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, LockResult, PoisonError, TryLockError, TryLockResult};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::backoff::{Backoff, Spin};
+use crate::lock::Lock;
+use crate::lockdep::{self, LockId, Tracked};
+use crate::shim::atomic::AtomicBool;
+use crate::shim::cell::UnsafeCell;
+#[cfg(feature = "stats")]
+use crate::stats::{LockStats, LockStatsSnapshot};
+#[cfg(debug_assertions)]
+use crate::threadid;
 
 /// A simple spinlock implementation.
-pub struct SpinLock<T> {
-    /// `AtomicBool` is used to track the lock's state (locked/unlocked).
-    locked: AtomicBool,
+///
+/// `B` decides what a thread does while it waits for the lock (see the
+/// `backoff` module). The default, `Spin`, simply spins.
+///
+/// Like `std::sync::Mutex`, the lock is *poisoned* when a thread panics while
+/// holding it: the data may have been left half-updated, so every later
+/// `lock()` returns an `Err(PoisonError)`. The guard is still inside the error
+/// and can be recovered with `PoisonError::into_inner` if the caller knows how
+/// to deal with the data. Poisoning can be switched off with `without_poisoning`.
+///
+/// The lock is not reentrant: a thread that calls `lock()` while it already
+/// holds the lock would wait for itself forever. In debug builds the lock
+/// remembers its owner and panics instead; use `ReentrantSpinLock` if a thread
+/// really needs to lock the same data more than once. Debug builds also catch
+/// two threads locking the same locks in opposite orders, see `lockdep`.
+///
+/// With the `model` cargo feature, the lock is built on the atomics of `model`,
+/// so that tests can check it in every interleaving.
+pub struct SpinLock<T, B = Spin> {
+    /// The lock's state, kept apart from the data so that guards which no longer
+    /// know about `T` (see `MappedSpinLockGuard`) can still release the lock.
+    raw: RawSpinLock,
     /// `UnsafeCell` provides interior mutability. It allows us to get a mutable
     /// reference to the data even when the `SpinLock` is behind an immutable reference.
     /// This is safe because we ensure exclusive access via the `locked` atomic.
     value: UnsafeCell<T>,
+    /// The backoff strategy only exists at the type level. `fn() -> B` keeps it
+    /// from affecting whether the lock is `Send` or `Sync`.
+    backoff: PhantomData<fn() -> B>,
+}
+
+/// The part of a `SpinLock` that does not depend on the protected data.
+struct RawSpinLock {
+    /// `AtomicBool` is used to track the lock's state (locked/unlocked).
+    locked: AtomicBool,
+    /// Set when a guard was dropped during a panic.
+    poisoned: AtomicBool,
+    /// Whether a panic while holding the lock poisons it at all.
+    poisoning: bool,
+    /// The thread holding the lock (see `threadid`), or 0. Only tracked in debug
+    /// builds, to catch a thread trying to lock the lock it already holds.
+    #[cfg(debug_assertions)]
+    owner: AtomicU64,
+    /// The lock's node in the lock-order graph of `lockdep`.
+    dep: LockId,
+    /// Contention counters, only kept with the `stats` cargo feature.
+    #[cfg(feature = "stats")]
+    stats: LockStats,
+}
+
+/// What a guard remembers about the moment it took the lock.
+#[derive(Clone, Copy)]
+struct Hold {
+    /// Whether the thread was already panicking when it took the lock. A panic
+    /// that started *before* we touched the data cannot have corrupted it.
+    panicking: bool,
+    /// When the lock was taken, to measure how long it is held.
+    #[cfg(feature = "stats")]
+    since: Instant,
 }
 
 /// A guard that provides access to the locked data.
 /// When the guard is dropped, the lock is automatically released.
-pub struct SpinLockGuard<'a, T> {
-    lock: &'a SpinLock<T>,
+pub struct SpinLockGuard<'a, T, B = Spin> {
+    /// `pub(crate)` so that `SpinCondvar` can re-lock the lock after waiting.
+    pub(crate) lock: &'a SpinLock<T, B>,
+    hold: Hold,
+    /// Opts out of the automatic `Send`/`Sync` impls, see below.
+    marker: PhantomData<*const ()>,
+}
+
+/// A guard that gives access to one part of the locked data, created with
+/// `SpinLockGuard::map`. It still holds the whole lock until it is dropped.
+pub struct MappedSpinLockGuard<'a, U> {
+    raw: &'a RawSpinLock,
+    hold: Hold,
+    value: NonNull<U>,
+    /// Tells the compiler that we behave like a `&'a mut U`.
+    marker: PhantomData<&'a mut U>,
+}
+
+/// A guard that keeps its `SpinLock` alive through an `Arc` instead of borrowing
+/// it, created with `lock_owned`. It has no lifetime parameter, so it can be
+/// returned from functions or moved into other threads.
+pub struct OwnedSpinLockGuard<T, B = Spin> {
+    lock: Arc<SpinLock<T, B>>,
+    hold: Hold,
+    /// Opts out of the automatic `Send`/`Sync` impls, see below.
+    marker: PhantomData<*const ()>,
 }
 
 // These `unsafe` impls are necessary to tell the compiler that `SpinLock<T>`
 // is thread-safe if `T` is `Send`. The `UnsafeCell` makes the compiler
 // conservative, but we know our implementation is sound.
-unsafe impl<T: Send> Send for SpinLock<T> {}
-unsafe impl<T: Send> Sync for SpinLock<T> {}
+unsafe impl<T: Send, B> Send for SpinLock<T, B> {}
+unsafe impl<T: Send, B> Sync for SpinLock<T, B> {}
+// Like `MutexGuard`: the guard must be dropped on the thread that locked, and
+// `&guard` hands out `&T`, so sharing the guard between threads requires
+// `T: Sync`. Without the marker it would be `Sync` whenever `T: Send`.
+unsafe impl<T: Sync, B> Sync for SpinLockGuard<'_, T, B> {}
+// A mapped guard hands out `&U` to whoever can see the guard, so sharing the
+// guard between threads requires `U: Sync`. The raw pointer makes it `!Send`.
+unsafe impl<U: Sync> Sync for MappedSpinLockGuard<'_, U> {}
+// The `Arc` alone would make the owned guard `Sync` whenever `T: Send`, which
+// would let several threads read a `T` that is not `Sync` through `&guard`.
+// Unlike the other guards it may be dropped on another thread, which is why
+// `lock_owned` keeps it out of the per-thread debug bookkeeping.
+unsafe impl<T: Send, B> Send for OwnedSpinLockGuard<T, B> {}
+unsafe impl<T: Send + Sync, B> Sync for OwnedSpinLockGuard<T, B> {}
 
-impl<T> SpinLock<T> {
-    /// Creates a new `SpinLock` protecting the given data.
-    pub const fn new(value: T) -> Self {
+impl Hold {
+    fn new() -> Self {
         Self {
-            locked: AtomicBool::new(false),
-            value: UnsafeCell::new(value),
+            panicking: thread::panicking(),
+            #[cfg(feature = "stats")]
+            since: Instant::now(),
         }
     }
+}
+
+impl RawSpinLock {
+    /// Makes a single attempt to take the lock.
+    fn try_acquire(&self) -> bool {
+        // A single attempt, so we use the "strong" `compare_exchange`: the weak
+        // version may fail spuriously, which would make `try_lock` report a
+        // held lock when it is actually free.
+        let acquired = self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if acquired {
+            #[cfg(debug_assertions)]
+            self.owner.store(threadid::current(), Ordering::Relaxed);
+            lockdep::locked(self);
+        }
+        acquired
+    }
+
+    /// Spins until the lock is taken, waiting with the backoff strategy `B`.
+    fn acquire<B: Backoff>(&self) {
+        // Spinning on a lock we hold ourselves would never end. Only the current
+        // thread can have stored its own identity, so a `Relaxed` load is enough.
+        #[cfg(debug_assertions)]
+        if self.owner.load(Ordering::Relaxed) == threadid::current() {
+            panic!(
+                "deadlock: thread '{}' tried to lock a SpinLock it already holds",
+                thread::current().name().unwrap_or("<unnamed>")
+            );
+        }
+        // Locking in the opposite order of another thread could deadlock.
+        lockdep::will_lock(self);
+
+        // Every acquisition starts with a fresh backoff state.
+        let mut backoff = B::default();
+        #[cfg(feature = "stats")]
+        let mut spins = 0;
 
-    /// Acquires the lock, spinning until it is available.
-    /// Returns a `SpinLockGuard` which allows access to the data.
-    pub fn lock(&self) -> SpinLockGuard<T> {
         // This is the "spin" part of the spinlock.
         // We loop continuously until we successfully acquire the lock.
         while self
@@ -46,30 +185,222 @@ impl<T> SpinLock<T> {
         {
             // `compare_exchange_weak` is used for performance in loops.
             // If it fails, it means the lock is currently held.
-            // We use `std::hint::spin_loop()` to inform the processor that we are
-            // in a busy-wait loop, which can improve performance.
-            hint::spin_loop();
+            // The backoff strategy decides how long to wait before trying again:
+            // the default `Spin` calls `std::hint::spin_loop()` to inform the
+            // processor that we are in a busy-wait loop.
+            backoff.snooze();
+            #[cfg(feature = "stats")]
+            {
+                spins += 1;
+            }
         }
 
+        #[cfg(debug_assertions)]
+        self.owner.store(threadid::current(), Ordering::Relaxed);
+        lockdep::locked(self);
+        #[cfg(feature = "stats")]
+        self.stats.record_acquisition(spins);
+    }
+
+    /// Releases the lock on behalf of a guard.
+    fn release(&self, hold: &Hold) {
+        #[cfg(feature = "stats")]
+        self.stats.record_hold(hold.since.elapsed());
+        // If we are unwinding from a panic that started while we held the lock,
+        // the data may be inconsistent: poison the lock before releasing it.
+        if self.poisoning && !hold.panicking && thread::panicking() {
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
+        #[cfg(debug_assertions)]
+        self.owner.store(0, Ordering::Relaxed);
+        lockdep::unlocked(self);
+        // The `Release` ordering ensures that any writes to the protected data
+        // "happen-before" the next thread acquires the lock, making our changes
+        // visible to it and preventing data races.
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+impl Tracked for RawSpinLock {
+    fn lock_id(&self) -> &LockId {
+        &self.dep
+    }
+
+    fn kind(&self) -> &'static str {
+        "SpinLock"
+    }
+}
+
+impl<T> SpinLock<T> {
+    /// Creates a new `SpinLock` protecting the given data.
+    pub const fn new(value: T) -> Self {
+        Self::with_backoff(value)
+    }
+}
+
+impl<T, B: Backoff> SpinLock<T, B> {
+    /// Creates a new `SpinLock` that waits using the backoff strategy `B`,
+    /// e.g. `SpinLock::<_, Exponential>::with_backoff(0)`.
+    pub const fn with_backoff(value: T) -> Self {
+        Self {
+            raw: RawSpinLock {
+                locked: AtomicBool::new(false),
+                poisoned: AtomicBool::new(false),
+                poisoning: true,
+                #[cfg(debug_assertions)]
+                owner: AtomicU64::new(0),
+                dep: LockId::new(),
+                #[cfg(feature = "stats")]
+                stats: LockStats::new(),
+            },
+            value: UnsafeCell::new(value),
+            backoff: PhantomData,
+        }
+    }
+
+    /// Turns off poisoning: a panic while holding the lock simply releases it,
+    /// and `lock()` never returns an error.
+    /// Usage: `SpinLock::new(0).without_poisoning()`.
+    pub const fn without_poisoning(mut self) -> Self {
+        self.raw.poisoning = false;
+        self
+    }
+
+    /// Returns `true` if a thread panicked while holding the lock.
+    pub fn is_poisoned(&self) -> bool {
+        self.raw.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Marks the lock as no longer poisoned, e.g. after the data was repaired
+    /// through the guard recovered from a `PoisonError`.
+    pub fn clear_poison(&self) {
+        self.raw.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the contention counters of this lock.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> LockStatsSnapshot {
+        self.raw.stats.snapshot()
+    }
+
+    /// Wraps a freshly acquired lock in a guard, reporting poisoning like `Mutex` does.
+    fn guard(&self) -> LockResult<SpinLockGuard<'_, T, B>> {
+        let guard = SpinLockGuard {
+            lock: self,
+            hold: Hold::new(),
+            marker: PhantomData,
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Acquires the lock, spinning until it is available.
+    /// Returns a `SpinLockGuard` which allows access to the data, or a
+    /// `PoisonError` containing the guard if the lock is poisoned.
+    pub fn lock(&self) -> LockResult<SpinLockGuard<'_, T, B>> {
+        self.raw.acquire::<B>();
         // Once we acquire the lock, we return a guard.
-        SpinLockGuard { lock: self }
+        self.guard()
+    }
+
+    /// Like `lock`, but the guard holds a clone of the `Arc` instead of a
+    /// reference, so it is not tied to the lifetime of `self`.
+    ///
+    /// In debug builds, the lock does not remember which thread took it this
+    /// way: the guard may be dropped on another thread, and the thread that took
+    /// it must be able to wait for that instead of reporting a self-deadlock.
+    /// For the same reason, `lockdep` checks the order in which the lock is
+    /// taken, but does not count it among the locks the thread holds.
+    pub fn lock_owned(self: &Arc<Self>) -> LockResult<OwnedSpinLockGuard<T, B>> {
+        self.raw.acquire::<B>();
+        #[cfg(debug_assertions)]
+        self.raw.owner.store(0, Ordering::Relaxed);
+        lockdep::unlocked(&self.raw);
+        let guard = OwnedSpinLockGuard {
+            lock: Arc::clone(self),
+            hold: Hold::new(),
+            marker: PhantomData,
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Attempts to acquire the lock without spinning.
+    /// Returns `Err(TryLockError::WouldBlock)` if another thread currently holds the lock.
+    pub fn try_lock(&self) -> TryLockResult<SpinLockGuard<'_, T, B>> {
+        if !self.raw.try_acquire() {
+            return Err(TryLockError::WouldBlock);
+        }
+        #[cfg(feature = "stats")]
+        self.raw.stats.record_acquisition(0);
+        Ok(self.guard()?)
+    }
+
+    /// Acquires the lock, spinning for at most `timeout`.
+    /// Returns `Err(TryLockError::WouldBlock)` if the lock could not be acquired
+    /// in time, which lets the caller back off instead of waiting forever on a
+    /// long-held lock.
+    pub fn lock_timeout(&self, timeout: Duration) -> TryLockResult<SpinLockGuard<'_, T, B>> {
+        // A timeout too large to represent as an `Instant` is as good as no timeout.
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            return Ok(self.lock()?);
+        };
+
+        let mut backoff = B::default();
+        #[cfg(feature = "stats")]
+        let mut spins = 0;
+        loop {
+            if self.raw.try_acquire() {
+                #[cfg(feature = "stats")]
+                self.raw.stats.record_acquisition(spins);
+                return Ok(self.guard()?);
+            }
+            // While the lock is held we only *read* the flag. Plain loads keep the
+            // cache line shared between the waiting cores, unlike a failing CAS.
+            while self.raw.locked.load(Ordering::Relaxed) {
+                if Instant::now() >= deadline {
+                    return Err(TryLockError::WouldBlock);
+                }
+                backoff.snooze();
+                #[cfg(feature = "stats")]
+                {
+                    spins += 1;
+                }
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the protected data.
+    /// No locking is needed: the `&mut self` borrow already proves that no
+    /// other thread can access the lock. Poisoning is ignored here; check
+    /// `is_poisoned` first if it matters.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Consumes the lock and returns the protected data, ignoring poisoning.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
     }
 }
 
 /// Implements the `Drop` trait to automatically release the lock when the
 /// `SpinLockGuard` goes out of scope. This is a crucial part of the lock guard pattern.
-impl<T> Drop for SpinLockGuard<'_, T> {
+impl<T, B> Drop for SpinLockGuard<'_, T, B> {
     fn drop(&mut self) {
-        // The `Release` ordering ensures that any writes to the protected data
-        // "happen-before" the next thread acquires the lock, making our changes
-        // visible to it and preventing data races.
-        self.lock.locked.store(false, Ordering::Release);
+        self.lock.raw.release(&self.hold);
     }
 }
 
 /// Implements `Deref` to allow the `SpinLockGuard` to be treated like a reference
 /// to the protected data.
-impl<T> Deref for SpinLockGuard<'_, T> {
+impl<T, B> Deref for SpinLockGuard<'_, T, B> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
         // Safety: The existence of the guard proves we have exclusive access.
@@ -80,9 +411,161 @@ impl<T> Deref for SpinLockGuard<'_, T> {
 
 /// Implements `DerefMut` to allow the `SpinLockGuard` to be treated like a mutable
 /// reference to the protected data.
-impl<T> DerefMut for SpinLockGuard<'_, T> {
+impl<T, B> DerefMut for SpinLockGuard<'_, T, B> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         // Safety: The existence of the guard proves we have exclusive access.
         unsafe { &mut *self.lock.value.get() }
     }
-}
\ No newline at end of file
+}
+
+impl<'a, T, B> SpinLockGuard<'a, T, B> {
+    /// Narrows the guard down to one part of the locked data, e.g. a single field:
+    /// `SpinLockGuard::map(guard, |state| &mut state.counter)`.
+    ///
+    /// This is an associated function rather than a method so that it cannot be
+    /// confused with a method of the same name on the locked data.
+    pub fn map<U, F>(orig: Self, f: F) -> MappedSpinLockGuard<'a, U>
+    where
+        F: FnOnce(&mut T) -> &mut U,
+    {
+        match Self::try_map(orig, |value| Some(f(value))) {
+            Ok(mapped) => mapped,
+            Err(_) => unreachable!("the closure always returns `Some`"),
+        }
+    }
+
+    /// Like `map`, but the closure may decline to produce a reference, in which
+    /// case the original guard is handed back unchanged.
+    pub fn try_map<U, F>(orig: Self, f: F) -> Result<MappedSpinLockGuard<'a, U>, Self>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        // Safety: The guard proves we have exclusive access to the data.
+        let value = match f(unsafe { &mut *orig.lock.value.get() }) {
+            Some(value) => NonNull::from(value),
+            None => return Err(orig),
+        };
+        let mapped = MappedSpinLockGuard {
+            raw: &orig.lock.raw,
+            hold: orig.hold,
+            value,
+            marker: PhantomData,
+        };
+        // The mapped guard takes over releasing the lock.
+        std::mem::forget(orig);
+        Ok(mapped)
+    }
+}
+
+impl<'a, U> MappedSpinLockGuard<'a, U> {
+    /// Narrows an already mapped guard down even further.
+    pub fn map<V, F>(orig: Self, f: F) -> MappedSpinLockGuard<'a, V>
+    where
+        F: FnOnce(&mut U) -> &mut V,
+    {
+        match Self::try_map(orig, |value| Some(f(value))) {
+            Ok(mapped) => mapped,
+            Err(_) => unreachable!("the closure always returns `Some`"),
+        }
+    }
+
+    /// Like `map`, but hands the guard back if the closure returns `None`.
+    pub fn try_map<V, F>(mut orig: Self, f: F) -> Result<MappedSpinLockGuard<'a, V>, Self>
+    where
+        F: FnOnce(&mut U) -> Option<&mut V>,
+    {
+        // Safety: The guard proves we have exclusive access to the data.
+        let value = match f(unsafe { orig.value.as_mut() }) {
+            Some(value) => NonNull::from(value),
+            None => return Err(orig),
+        };
+        let mapped = MappedSpinLockGuard {
+            raw: orig.raw,
+            hold: orig.hold,
+            value,
+            marker: PhantomData,
+        };
+        std::mem::forget(orig);
+        Ok(mapped)
+    }
+}
+
+impl<U> Drop for MappedSpinLockGuard<'_, U> {
+    fn drop(&mut self) {
+        self.raw.release(&self.hold);
+    }
+}
+
+impl<U> Deref for MappedSpinLockGuard<'_, U> {
+    type Target = U;
+    fn deref(&self) -> &Self::Target {
+        // Safety: The pointer came from the data of a lock we still hold.
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<U> DerefMut for MappedSpinLockGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The pointer came from the data of a lock we still hold.
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<T, B> Drop for OwnedSpinLockGuard<T, B> {
+    fn drop(&mut self) {
+        self.lock.raw.release(&self.hold);
+    }
+}
+
+impl<T, B> Deref for OwnedSpinLockGuard<T, B> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: The existence of the guard proves we have exclusive access.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T, B> DerefMut for OwnedSpinLockGuard<T, B> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: The existence of the guard proves we have exclusive access.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T, B: Backoff> Lock<T> for SpinLock<T, B> {
+    type Guard<'a>
+        = SpinLockGuard<'a, T, B>
+    where
+        Self: 'a;
+
+    fn new(value: T) -> Self {
+        SpinLock::with_backoff(value)
+    }
+
+    fn lock(&self) -> LockResult<Self::Guard<'_>> {
+        SpinLock::lock(self)
+    }
+
+    fn try_lock(&self) -> TryLockResult<Self::Guard<'_>> {
+        SpinLock::try_lock(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owned_guard_can_be_released_on_another_thread() {
+        let lock = Arc::new(SpinLock::new(0));
+        let guard = lock.lock_owned().unwrap();
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(guard);
+        });
+        // Waits for the other thread, instead of taking the lock for our own.
+        *lock.lock().unwrap() += 1;
+        releaser.join().unwrap();
+        assert_eq!(*lock.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Number of buckets in the hold-time histogram. Bucket 0 counts holds of 0ns,
+/// bucket `i` counts holds of `2^(i-1)` up to `2^i - 1` nanoseconds, and the last
+/// bucket also collects everything longer.
+pub const HOLD_TIME_BUCKETS: usize = 32;
+
+/// Contention counters kept by a lock when the `stats` feature is enabled.
+///
+/// All counters are updated with `Relaxed` atomics: they are only statistics,
+/// nothing else is synchronised through them.
+pub(crate) struct LockStats {
+    acquisitions: AtomicU64,
+    contended_acquisitions: AtomicU64,
+    spin_iterations: AtomicU64,
+    max_hold_nanos: AtomicU64,
+    hold_time_histogram: [AtomicU64; HOLD_TIME_BUCKETS],
+}
+
+/// A point-in-time copy of a lock's contention counters, see `SpinLock::stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockStatsSnapshot {
+    /// How often the lock was taken.
+    pub acquisitions: u64,
+    /// How many of those acquisitions found the lock already held at least once.
+    pub contended_acquisitions: u64,
+    /// How many times waiting threads backed off (failed attempts) in total.
+    pub spin_iterations: u64,
+    /// The longest time any thread held the lock.
+    pub max_hold_time: Duration,
+    /// How many holds fell into each bucket, see `HOLD_TIME_BUCKETS`.
+    pub hold_time_histogram: [u64; HOLD_TIME_BUCKETS],
+}
+
+impl LockStats {
+    pub(crate) const fn new() -> Self {
+        Self {
+            acquisitions: AtomicU64::new(0),
+            contended_acquisitions: AtomicU64::new(0),
+            spin_iterations: AtomicU64::new(0),
+            max_hold_nanos: AtomicU64::new(0),
+            hold_time_histogram: [const { AtomicU64::new(0) }; HOLD_TIME_BUCKETS],
+        }
+    }
+
+    /// Records one acquisition that had to back off `spins` times first.
+    pub(crate) fn record_acquisition(&self, spins: u64) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if spins > 0 {
+            self.contended_acquisitions.fetch_add(1, Ordering::Relaxed);
+            self.spin_iterations.fetch_add(spins, Ordering::Relaxed);
+        }
+    }
+
+    /// Records how long the lock was held before being released.
+    pub(crate) fn record_hold(&self, held: Duration) {
+        let nanos = u64::try_from(held.as_nanos()).unwrap_or(u64::MAX);
+        self.max_hold_nanos.fetch_max(nanos, Ordering::Relaxed);
+        // The bucket is the number of significant bits, i.e. `floor(log2(nanos)) + 1`.
+        let bucket = (u64::BITS - nanos.leading_zeros()) as usize;
+        self.hold_time_histogram[bucket.min(HOLD_TIME_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> LockStatsSnapshot {
+        LockStatsSnapshot {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended_acquisitions: self.contended_acquisitions.load(Ordering::Relaxed),
+            spin_iterations: self.spin_iterations.load(Ordering::Relaxed),
+            max_hold_time: Duration::from_nanos(self.max_hold_nanos.load(Ordering::Relaxed)),
+            hold_time_histogram: self
+                .hold_time_histogram
+                .each_ref()
+                .map(|bucket| bucket.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Prints a human-readable contention report.
+impl fmt::Display for LockStatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let contended_percent = if self.acquisitions == 0 {
+            0.0
+        } else {
+            100.0 * self.contended_acquisitions as f64 / self.acquisitions as f64
+        };
+        writeln!(f, "Acquisitions:           {}", self.acquisitions)?;
+        writeln!(
+            f,
+            "Contended acquisitions: {} ({:.1}%)",
+            self.contended_acquisitions, contended_percent
+        )?;
+        writeln!(f, "Total spin iterations:  {}", self.spin_iterations)?;
+        writeln!(f, "Max hold time:          {:?}", self.max_hold_time)?;
+        writeln!(f, "Hold time histogram:")?;
+        for (bucket, &count) in self.hold_time_histogram.iter().enumerate() {
+            if count == 0 {
+                continue;
+            }
+            let low = if bucket == 0 { 0 } else { 1u64 << (bucket - 1) };
+            if bucket == HOLD_TIME_BUCKETS - 1 {
+                writeln!(f, "  >= {:>10?}: {}", Duration::from_nanos(low), count)?;
+            } else {
+                writeln!(
+                    f,
+                    "  <  {:>10?}: {}",
+                    Duration::from_nanos(1u64 << bucket),
+                    count
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
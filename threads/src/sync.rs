@@ -0,0 +1,491 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::hint;
+use std::mem::{self, ManuallyDrop};
+use std::ops::Deref;
+use std::process;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering, fence};
+
+/// More references than this are treated like a leak loop gone wrong. Stopping
+/// well below `usize::MAX` leaves room for the increments of other threads that
+/// are racing past the check, so the count can never wrap around to 0.
+const MAX_REFCOUNT: usize = isize::MAX as usize;
+
+/// The allocation shared by all `MyArc`s and `Weak`s of one value.
+struct ArcInner<T> {
+    /// The number of `MyArc`s.
+    strong: AtomicUsize,
+    /// The number of `Weak`s, plus one shared by all `MyArc`s together while
+    /// there are any. `usize::MAX` while `get_mut` checks for uniqueness.
+    weak: AtomicUsize,
+    /// Dropped when `strong` reaches 0, while the allocation lives on until
+    /// `weak` does.
+    data: UnsafeCell<ManuallyDrop<T>>,
+}
+
+/// A thread-safe reference-counted pointer, like `std::sync::Arc`.
+///
+/// Cloning a `MyArc` only increments a counter; the value is dropped when the
+/// last `MyArc` goes away. A `Weak` points to the value without keeping it
+/// alive, which breaks reference cycles, such as between a parent that owns its
+/// children and children that point back to their parent.
+pub struct MyArc<T> {
+    ptr: NonNull<ArcInner<T>>,
+}
+
+/// A pointer to the value of a `MyArc` that does not keep the value alive, see
+/// `MyArc::downgrade`. It keeps the allocation itself alive, so `upgrade` can
+/// always tell whether the value is still there.
+pub struct Weak<T> {
+    ptr: NonNull<ArcInner<T>>,
+}
+
+// Safety: A `MyArc<T>` hands out `&T` to several threads, so `T` must be `Sync`,
+// and whichever thread drops the last one drops the `T`, so `T` must be `Send`.
+unsafe impl<T: Send + Sync> Send for MyArc<T> {}
+unsafe impl<T: Send + Sync> Sync for MyArc<T> {}
+// Safety: A `Weak<T>` can become a `MyArc<T>` on any thread.
+unsafe impl<T: Send + Sync> Send for Weak<T> {}
+unsafe impl<T: Send + Sync> Sync for Weak<T> {}
+
+impl<T> MyArc<T> {
+    /// Moves `data` to the heap, with a reference count of 1.
+    pub fn new(data: T) -> MyArc<T> {
+        let inner = Box::new(ArcInner {
+            strong: AtomicUsize::new(1),
+            weak: AtomicUsize::new(1),
+            data: UnsafeCell::new(ManuallyDrop::new(data)),
+        });
+        MyArc {
+            ptr: NonNull::from(Box::leak(inner)),
+        }
+    }
+
+    fn inner(&self) -> &ArcInner<T> {
+        // Safety: The allocation lives as long as there is a `MyArc`.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Returns the number of `MyArc`s that point to the value. Other threads may
+    /// change it right away.
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().strong.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of `Weak`s that point to the value. Other threads may
+    /// change it right away.
+    pub fn weak_count(this: &Self) -> usize {
+        match this.inner().weak.load(Ordering::Relaxed) {
+            // Another `MyArc` is in `get_mut`, which only succeeds without `Weak`s.
+            usize::MAX => 0,
+            // Leave out the reference shared by the `MyArc`s.
+            weak => weak - 1,
+        }
+    }
+
+    /// Returns `true` if both point to the same value.
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+
+    /// Creates a `Weak` that points to the value.
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        let weak = &this.inner().weak;
+        let mut count = weak.load(Ordering::Relaxed);
+        loop {
+            // `get_mut` has locked the count; it unlocks it again right away.
+            if count == usize::MAX {
+                hint::spin_loop();
+                count = weak.load(Ordering::Relaxed);
+                continue;
+            }
+            if count > MAX_REFCOUNT {
+                process::abort();
+            }
+            // `Acquire` pairs with the `Release` that unlocks the count in
+            // `get_mut`, so a `&mut T` handed out before is done with.
+            match weak.compare_exchange_weak(count, count + 1, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => return Weak { ptr: this.ptr },
+                Err(current) => count = current,
+            }
+        }
+    }
+
+    /// Returns a mutable reference to the value if no other `MyArc` or `Weak`
+    /// points to it.
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        let inner = this.inner();
+        // Lock the weak count, so no `Weak` can be created from another `MyArc`
+        // while we look at the strong count. `Acquire` pairs with the `Release`
+        // of dropped `Weak`s, whose `upgrade`d `MyArc`s may have used the value.
+        if inner
+            .weak
+            .compare_exchange(1, usize::MAX, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+        let is_unique = inner.strong.load(Ordering::Relaxed) == 1;
+        inner.weak.store(1, Ordering::Release);
+        if !is_unique {
+            return None;
+        }
+        // Pairs with the `Release` of dropped `MyArc`s, which may have used the value.
+        fence(Ordering::Acquire);
+        // Safety: Nobody else can reach the value, and `this` is borrowed mutably.
+        Some(unsafe { &mut *this.inner().data.get() })
+    }
+
+    /// Returns a mutable reference to the value, cloning it first if other
+    /// `MyArc`s point to it (copy-on-write).
+    ///
+    /// `Weak`s keep pointing to the old value. If there are no other `MyArc`s,
+    /// the value is moved to a new allocation instead of cloned, and the `Weak`s
+    /// can no longer upgrade.
+    pub fn make_mut(this: &mut Self) -> &mut T
+    where
+        T: Clone,
+    {
+        let inner = this.inner();
+        // Claim the value by setting the strong count to 0: no `Weak` can upgrade
+        // any more. `Acquire` pairs with the `Release` of dropped `MyArc`s.
+        if inner
+            .strong
+            .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // Other `MyArc`s share the value: give them the old one.
+            *this = MyArc::new(T::clone(this));
+        } else if inner.weak.load(Ordering::Relaxed) != 1 {
+            // `Weak`s point to the value. They have already seen the strong count
+            // drop to 0, so move the value away from them.
+            // Safety: The strong count is 0, so nobody else uses the value, and
+            // `old` is forgotten below instead of dropping the value again.
+            let data = unsafe { ManuallyDrop::take(&mut *inner.data.get()) };
+            let old = mem::replace(this, MyArc::new(data));
+            // Give up the reference that the `MyArc`s shared, without dropping the
+            // moved-out value; the last `Weak` frees the allocation.
+            drop(Weak { ptr: old.ptr });
+            mem::forget(old);
+        } else {
+            // We were the only reference after all.
+            inner.strong.store(1, Ordering::Release);
+        }
+        // Safety: `this` is the only reference to its value now.
+        unsafe { &mut *this.inner().data.get() }
+    }
+
+    /// Returns the value if this is the only `MyArc`, and `this` otherwise.
+    ///
+    /// `Weak`s do not count: they can no longer upgrade once this succeeds.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        let inner = this.inner();
+        if inner
+            .strong
+            .compare_exchange(1, 0, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(this);
+        }
+        // Pairs with the `Release` of dropped `MyArc`s, which may have used the value.
+        fence(Ordering::Acquire);
+        // Safety: The strong count is 0, so nobody else uses the value, and
+        // `this` is forgotten below instead of dropping the value again.
+        let data = unsafe { ManuallyDrop::take(&mut *inner.data.get()) };
+        let ptr = this.ptr;
+        mem::forget(this);
+        // Give up the reference that the `MyArc`s shared.
+        drop(Weak { ptr });
+        Ok(data)
+    }
+}
+
+impl<T> Weak<T> {
+    fn inner(&self) -> &ArcInner<T> {
+        // Safety: The allocation lives as long as there is a `Weak`.
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Returns a `MyArc` to the value, or `None` if it has already been dropped.
+    ///
+    /// The strong count is only incremented if it is not 0 yet, in a single
+    /// compare-and-swap, so this cannot resurrect a value that the last
+    /// `MyArc` is dropping on another thread at the same time.
+    pub fn upgrade(&self) -> Option<MyArc<T>> {
+        let strong = &self.inner().strong;
+        let mut count = strong.load(Ordering::Relaxed);
+        loop {
+            if count == 0 {
+                return None;
+            }
+            if count > MAX_REFCOUNT {
+                process::abort();
+            }
+            match strong.compare_exchange_weak(
+                count,
+                count + 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(MyArc { ptr: self.ptr }),
+                Err(current) => count = current,
+            }
+        }
+    }
+
+    /// Returns the number of `MyArc`s that point to the value.
+    pub fn strong_count(&self) -> usize {
+        self.inner().strong.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Deref for MyArc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: The value lives as long as there is a `MyArc`, and it is only
+        // mutated through `get_mut` and `make_mut`, which need the only one.
+        unsafe { &*self.inner().data.get() }
+    }
+}
+
+impl<T> Clone for MyArc<T> {
+    fn clone(&self) -> Self {
+        // `Relaxed` is enough: we already have a reference, so the value cannot
+        // go away, and the new one is only passed on through `self`'s thread.
+        if self.inner().strong.fetch_add(1, Ordering::Relaxed) > MAX_REFCOUNT {
+            process::abort();
+        }
+        MyArc { ptr: self.ptr }
+    }
+}
+
+impl<T> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        if self.inner().weak.fetch_add(1, Ordering::Relaxed) > MAX_REFCOUNT {
+            process::abort();
+        }
+        Weak { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for MyArc<T> {
+    fn drop(&mut self) {
+        // `Release`: our uses of the value happen before the drop of whoever
+        // decrements to 0, which then needs the `Acquire` fence to see them.
+        if self.inner().strong.fetch_sub(1, Ordering::Release) == 1 {
+            fence(Ordering::Acquire);
+            // Safety: That was the last `MyArc`, so nobody uses the value any more.
+            unsafe { ManuallyDrop::drop(&mut *self.inner().data.get()) };
+            // Give up the reference that the `MyArc`s shared.
+            drop(Weak { ptr: self.ptr });
+        }
+    }
+}
+
+impl<T> Drop for Weak<T> {
+    fn drop(&mut self) {
+        // The same `Release`/`Acquire` pairing as for `MyArc`, for the allocation.
+        if self.inner().weak.fetch_sub(1, Ordering::Release) == 1 {
+            fence(Ordering::Acquire);
+            // Safety: The value has been dropped and this was the last reference.
+            drop(unsafe { Box::from_raw(self.ptr.as_ptr()) });
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for MyArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MyArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("(Weak)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Counts its own drops, to check that every value is dropped exactly once.
+    #[derive(Debug)]
+    struct DropCounter {
+        id: usize,
+        drops: Arc<AtomicUsize>,
+    }
+
+    impl DropCounter {
+        fn new(id: usize, drops: &Arc<AtomicUsize>) -> Self {
+            Self {
+                id,
+                drops: Arc::clone(drops),
+            }
+        }
+    }
+
+    impl Clone for DropCounter {
+        fn clone(&self) -> Self {
+            Self::new(self.id, &self.drops)
+        }
+    }
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.drops.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn get_mut_only_on_unique_handles() {
+        let mut value = MyArc::new(vec![1, 2, 3]);
+        MyArc::get_mut(&mut value).unwrap().push(4);
+
+        let other = MyArc::clone(&value);
+        assert!(MyArc::get_mut(&mut value).is_none());
+        drop(other);
+
+        let weak = MyArc::downgrade(&value);
+        assert!(MyArc::get_mut(&mut value).is_none());
+        drop(weak);
+
+        MyArc::get_mut(&mut value).unwrap().push(5);
+        assert_eq!(*value, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn make_mut_changes_a_unique_value_in_place() {
+        let mut value = MyArc::new(vec![1]);
+        let before = &*value as *const Vec<i32>;
+        MyArc::make_mut(&mut value).push(2);
+        assert_eq!(&*value as *const Vec<i32>, before);
+        assert_eq!(*value, [1, 2]);
+    }
+
+    #[test]
+    fn make_mut_copies_a_shared_value() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut value = MyArc::new(DropCounter::new(1, &drops));
+        let snapshot = MyArc::clone(&value);
+        MyArc::make_mut(&mut value).id = 2;
+        assert!(!MyArc::ptr_eq(&value, &snapshot));
+        assert_eq!((value.id, snapshot.id), (2, 1));
+        assert_eq!(MyArc::strong_count(&snapshot), 1);
+        drop((value, snapshot));
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn make_mut_moves_a_value_away_from_weaks() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut value = MyArc::new(DropCounter::new(1, &drops));
+        let weak = MyArc::downgrade(&value);
+        MyArc::make_mut(&mut value).id = 2;
+        assert!(weak.upgrade().is_none());
+        assert_eq!(MyArc::weak_count(&value), 0);
+        assert_eq!(drops.load(Ordering::Relaxed), 0, "moved, not cloned");
+        drop(weak);
+        drop(value);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn try_unwrap_only_succeeds_for_the_last_handle() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let value = MyArc::new(DropCounter::new(1, &drops));
+        let other = MyArc::clone(&value);
+        let value = MyArc::try_unwrap(value).unwrap_err();
+        drop(other);
+
+        let weak = MyArc::downgrade(&value);
+        let tracked = MyArc::try_unwrap(value).unwrap();
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.strong_count(), 0);
+        drop(weak);
+        assert_eq!(drops.load(Ordering::Relaxed), 0);
+        drop(tracked);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    /// Lets threads upgrade a `Weak` while the main thread drops the last
+    /// `MyArc`. An upgrade must either see the value intact or fail; it may
+    /// never bring back a value that is being dropped.
+    #[test]
+    fn weak_upgrade_races_with_the_last_drop() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        for round in 0..500 {
+            let value = MyArc::new(DropCounter::new(round, &drops));
+            let weak = MyArc::downgrade(&value);
+            thread::scope(|s| {
+                for _ in 0..3 {
+                    let weak = weak.clone();
+                    s.spawn(move || {
+                        // Bounded, so that the upgraders cannot keep the value
+                        // alive forever by handing it to each other.
+                        for _ in 0..1_000 {
+                            match weak.upgrade() {
+                                Some(value) => assert_eq!(value.id, round),
+                                None => break,
+                            }
+                        }
+                    });
+                }
+                thread::yield_now();
+                drop(value);
+            });
+            assert!(weak.upgrade().is_none());
+            assert_eq!(drops.load(Ordering::Relaxed), round + 1);
+        }
+    }
+
+    /// A parent owns its children, and each child points back to its parent.
+    struct Parent {
+        children: Mutex<Vec<MyArc<Child>>>,
+        _counter: DropCounter,
+    }
+
+    struct Child {
+        parent: Weak<Parent>,
+        _counter: DropCounter,
+    }
+
+    #[test]
+    fn weak_back_pointers_do_not_leak_a_cycle() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let parent = MyArc::new(Parent {
+            children: Mutex::new(Vec::new()),
+            _counter: DropCounter::new(0, &drops),
+        });
+        for id in 1..=2 {
+            let child = MyArc::new(Child {
+                parent: MyArc::downgrade(&parent),
+                _counter: DropCounter::new(id, &drops),
+            });
+            parent.children.lock().unwrap().push(child);
+        }
+        assert_eq!(MyArc::strong_count(&parent), 1);
+        assert_eq!(MyArc::weak_count(&parent), 2);
+
+        let first = MyArc::clone(&parent.children.lock().unwrap()[0]);
+        assert!(first.parent.upgrade().is_some());
+        drop(parent);
+        // The parent and the child nobody else held are gone.
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+        assert!(first.parent.upgrade().is_none());
+        assert_eq!(MyArc::strong_count(&first), 1);
+        drop(first);
+        assert_eq!(drops.load(Ordering::Relaxed), 3);
+    }
+}
@@ -0,0 +1,19 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The next number to hand out. Zero is never handed out, so locks can use it
+/// to mean "no owner".
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static ID: u64 = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns a number that identifies the current thread.
+///
+/// This plays the role of `std::thread::ThreadId`, which we cannot store in an
+/// atomic because turning it into an integer (`ThreadId::as_u64`) is still
+/// unstable. Like a `ThreadId`, the number is never reused, not even after the
+/// thread has exited.
+pub(crate) fn current() -> u64 {
+    ID.with(|id| *id)
+}
@@ -0,0 +1,162 @@
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{LockResult, TryLockError, TryLockResult};
+
+use crate::backoff::{Backoff, SpinThenYield};
+use crate::lock::Lock;
+use crate::lockdep::{self, LockId, Tracked};
+
+/// A fair spinlock that serves waiters in FIFO order.
+///
+/// It works like the ticket dispenser at a deli counter: every thread that
+/// wants the lock draws the next ticket number, then waits until the
+/// "now serving" display shows its number. Unlike `SpinLock`, where whoever
+/// wins the next `compare_exchange` gets the lock, no thread can be overtaken.
+pub struct TicketLock<T> {
+    /// The ticket that will be handed to the next thread calling `lock()`.
+    next_ticket: AtomicUsize,
+    /// The ticket of the thread that currently holds (or may take) the lock.
+    now_serving: AtomicUsize,
+    /// The lock's node in the lock-order graph of `lockdep`.
+    dep: LockId,
+    value: UnsafeCell<T>,
+}
+
+/// A guard that provides access to the data locked by a `TicketLock`.
+/// When the guard is dropped, the lock is handed to the next ticket holder.
+pub struct TicketLockGuard<'a, T> {
+    lock: &'a TicketLock<T>,
+    /// Opts out of the automatic `Send`/`Sync` impls, see below.
+    marker: PhantomData<*const ()>,
+}
+
+// Same reasoning as for `SpinLock`: exclusive access is enforced by the tickets.
+unsafe impl<T: Send> Send for TicketLock<T> {}
+unsafe impl<T: Send> Sync for TicketLock<T> {}
+// As for `SpinLockGuard`: `&guard` hands out `&T`, so sharing the guard between
+// threads requires `T: Sync`.
+unsafe impl<T: Sync> Sync for TicketLockGuard<'_, T> {}
+
+impl<T> TicketLock<T> {
+    /// Creates a new `TicketLock` protecting the given data.
+    pub const fn new(value: T) -> Self {
+        Self {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+            dep: LockId::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires the lock, waiting for every thread that arrived earlier.
+    pub fn lock(&self) -> TicketLockGuard<'_, T> {
+        lockdep::will_lock(self);
+        // Drawing a ticket always succeeds, so there is no CAS loop here.
+        // `Relaxed` is enough: the ticket number itself protects no data.
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+
+        // All waiters spin on `now_serving` with plain loads. The `Acquire`
+        // pairs with the `Release` in the guard's `Drop`.
+        // Yielding after a while matters for fair locks: the lock is handed to one
+        // *specific* waiter, and nobody can make progress while it is descheduled.
+        let mut backoff = SpinThenYield::<100>::default();
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            backoff.snooze();
+        }
+
+        lockdep::locked(self);
+        TicketLockGuard {
+            lock: self,
+            marker: PhantomData,
+        }
+    }
+
+    /// Attempts to acquire the lock without waiting.
+    /// Returns `None` if the lock is held or other threads are already queued.
+    pub fn try_lock(&self) -> Option<TicketLockGuard<'_, T>> {
+        // The lock is free exactly when the next ticket to be drawn is the one
+        // being served. We only take a ticket if that is still the case, so we
+        // never join the queue behind someone else.
+        let serving = self.now_serving.load(Ordering::Acquire);
+        self.next_ticket
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .ok()?;
+        lockdep::locked(self);
+        Some(TicketLockGuard {
+            lock: self,
+            marker: PhantomData,
+        })
+    }
+
+    /// Returns a mutable reference to the protected data.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    /// Consumes the lock and returns the protected data.
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T> Tracked for TicketLock<T> {
+    fn lock_id(&self) -> &LockId {
+        &self.dep
+    }
+
+    fn kind(&self) -> &'static str {
+        "TicketLock"
+    }
+}
+
+impl<T> Lock<T> for TicketLock<T> {
+    type Guard<'a>
+        = TicketLockGuard<'a, T>
+    where
+        Self: 'a;
+
+    fn new(value: T) -> Self {
+        TicketLock::new(value)
+    }
+
+    fn lock(&self) -> LockResult<Self::Guard<'_>> {
+        Ok(TicketLock::lock(self))
+    }
+
+    fn try_lock(&self) -> TryLockResult<Self::Guard<'_>> {
+        TicketLock::try_lock(self).ok_or(TryLockError::WouldBlock)
+    }
+}
+
+/// Releasing the lock means "serving" the next ticket.
+impl<T> Drop for TicketLockGuard<'_, T> {
+    fn drop(&mut self) {
+        lockdep::unlocked(self.lock);
+        // Only the lock holder ever writes `now_serving`, so a `fetch_add` is not
+        // even necessary, but it keeps the intent obvious. `Release` publishes our
+        // writes to the protected data to the next ticket holder.
+        self.lock.now_serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+impl<T> Deref for TicketLockGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // Safety: Only the thread whose ticket is being served holds a guard.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for TicketLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // Safety: Only the thread whose ticket is being served holds a guard.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
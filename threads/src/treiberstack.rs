@@ -0,0 +1,113 @@
+use std::mem::ManuallyDrop;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::hazard::{self, HazardPointer};
+
+struct Node<T> {
+    /// Taken out by the thread that pops the node, so never dropped with it.
+    value: ManuallyDrop<T>,
+    /// Set once before the node is pushed, never changed afterwards.
+    next: *mut Node<T>,
+}
+
+// Safety: `next` is only followed while the node is reachable or protected.
+unsafe impl<T: Send> Send for Node<T> {}
+
+/// A lock-free LIFO stack (Treiber).
+///
+/// `head` points to the top node. Both `push` and `pop` read the top, prepare
+/// the new top, and swing `head` over with a compare-and-swap, retrying if
+/// another thread got there first.
+///
+/// Popped nodes are freed through hazard pointers. Besides protecting readers
+/// from freed memory, that also rules out the ABA problem: a node cannot be
+/// freed and reused at the same address while a thread is about to
+/// compare-and-swap against it.
+pub struct TreiberStack<T> {
+    head: AtomicPtr<Node<T>>,
+}
+
+unsafe impl<T: Send> Send for TreiberStack<T> {}
+unsafe impl<T: Send> Sync for TreiberStack<T> {}
+
+impl<T: Send> TreiberStack<T> {
+    /// Creates an empty stack.
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Puts an item on top of the stack.
+    pub fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(Node {
+            value: ManuallyDrop::new(value),
+            next: ptr::null_mut(),
+        }));
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // Safety: The node is not published yet, so it is still ours.
+            unsafe { (*node).next = head };
+            // `Release` publishes the node to the thread that pops it.
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+
+    /// Removes the item on top of the stack, if there is one.
+    pub fn pop(&self) -> Option<T> {
+        let hazard = HazardPointer::new();
+        loop {
+            let head = hazard.protect(&self.head);
+            if head.is_null() {
+                return None;
+            }
+            // Safety: `head` is protected, so it has not been freed.
+            let next = unsafe { (*head).next };
+            if self
+                .head
+                .compare_exchange(head, next, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                // Safety: We unlinked the node, so we are the only thread that
+                // takes its value out. It stays protected until `reset`.
+                let value =
+                    unsafe { ManuallyDrop::into_inner(ptr::read(&raw const (*head).value)) };
+                hazard.reset();
+                // Safety: The node is unreachable now.
+                unsafe { hazard::retire(head) };
+                return Some(value);
+            }
+        }
+    }
+
+    /// Returns `true` if the stack was empty at the time of the call.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+}
+
+impl<T: Send> Default for TreiberStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drops the items that are still on the stack and frees all nodes.
+impl<T> Drop for TreiberStack<T> {
+    fn drop(&mut self) {
+        let mut next = *self.head.get_mut();
+        while !next.is_null() {
+            // Safety: We have exclusive access, and every node holds a value.
+            let mut node = unsafe { Box::from_raw(next) };
+            unsafe { ManuallyDrop::drop(&mut node.value) };
+            next = node.next;
+        }
+    }
+}
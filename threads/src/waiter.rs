@@ -0,0 +1,52 @@
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use crate::shim::atomic::AtomicBool;
+use crate::shim::thread::{self, Thread};
+
+/// A parked thread waiting in the queue of a `ParkingLock` or `SpinCondvar`.
+pub(crate) struct Waiter {
+    thread: Thread,
+    /// Set by the waking thread right before it unparks us. `thread::park`
+    /// may return spuriously, so this flag is what actually tells us to wake up.
+    woken: AtomicBool,
+}
+
+impl Waiter {
+    /// Creates a waiter for the current thread.
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Self {
+            thread: thread::current(),
+            woken: AtomicBool::new(false),
+        })
+    }
+
+    /// Parks the current thread until `wake` is called.
+    pub(crate) fn wait(&self) {
+        while !self.woken.load(Ordering::Acquire) {
+            thread::park();
+        }
+    }
+
+    /// Parks the current thread until `wake` is called or the deadline passes.
+    /// Returns `false` if the deadline passed without a wake-up.
+    pub(crate) fn wait_until(&self, deadline: Instant) -> bool {
+        while !self.woken.load(Ordering::Acquire) {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            thread::park_timeout(deadline - now);
+        }
+        true
+    }
+
+    /// Wakes up the waiting thread.
+    pub(crate) fn wake(&self) {
+        // The waiter may return (and drop its `Arc`) as soon as it sees the flag.
+        // Our caller still holds an `Arc` of its own, so `self` stays valid.
+        self.woken.store(true, Ordering::Release);
+        self.thread.unpark();
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use threads::hazard;
+use threads::msqueue::MsQueue;
+use threads::treiberstack::TreiberStack;
+
+/// Pushes disjoint ranges of numbers from several producers while several
+/// consumers pop, and checks that every number is delivered exactly once.
+fn stress<Q: Sync>(
+    queue: Q,
+    push: impl Fn(&Q, u64) + Sync,
+    pop: impl Fn(&Q) -> Option<u64> + Sync,
+) {
+    const PRODUCERS: u64 = 4;
+    const CONSUMERS: usize = 4;
+    const ITEMS: u64 = 50_000;
+    let total = PRODUCERS * ITEMS;
+    let popped = AtomicU64::new(0);
+
+    let mut received: Vec<u64> = thread::scope(|s| {
+        for producer in 0..PRODUCERS {
+            let (queue, push) = (&queue, &push);
+            s.spawn(move || {
+                for i in 0..ITEMS {
+                    push(queue, producer * ITEMS + i);
+                }
+            });
+        }
+        let consumers: Vec<_> = (0..CONSUMERS)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut items = Vec::new();
+                    while popped.load(Ordering::Relaxed) < total {
+                        match pop(&queue) {
+                            Some(item) => {
+                                popped.fetch_add(1, Ordering::Relaxed);
+                                items.push(item);
+                            }
+                            None => thread::yield_now(),
+                        }
+                    }
+                    items
+                })
+            })
+            .collect();
+        consumers
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    received.sort_unstable();
+    assert!(received.iter().copied().eq(0..total));
+    // Free the nodes that are still waiting for a hazard pointer scan.
+    hazard::reclaim();
+}
+
+#[test]
+fn msqueue_delivers_every_item_exactly_once() {
+    stress(
+        MsQueue::new(),
+        |queue, item| queue.push(item),
+        |queue| queue.pop(),
+    );
+}
+
+#[test]
+fn treiberstack_delivers_every_item_exactly_once() {
+    stress(
+        TreiberStack::new(),
+        |stack, item| stack.push(item),
+        |stack| stack.pop(),
+    );
+}
+
+#[test]
+fn msqueue_is_fifo_and_treiberstack_is_lifo() {
+    let (queue, stack) = (MsQueue::new(), TreiberStack::new());
+    assert!(queue.is_empty() && stack.is_empty());
+    for i in 1..=3 {
+        queue.push(i);
+        stack.push(i);
+    }
+    assert_eq!(
+        [queue.pop(), queue.pop(), queue.pop()],
+        [Some(1), Some(2), Some(3)]
+    );
+    assert_eq!(
+        [stack.pop(), stack.pop(), stack.pop()],
+        [Some(3), Some(2), Some(1)]
+    );
+    assert_eq!((queue.pop(), stack.pop()), (None, None));
+    assert!(queue.is_empty() && stack.is_empty());
+}
+
+#[test]
+fn unpopped_items_are_dropped_with_the_queue() {
+    let item = Arc::new(());
+    let queue = MsQueue::new();
+    let stack = TreiberStack::new();
+    for _ in 0..2 {
+        queue.push(Arc::clone(&item));
+        stack.push(Arc::clone(&item));
+    }
+    drop(queue.pop());
+    drop(stack.pop());
+    drop(queue);
+    drop(stack);
+    assert_eq!(Arc::strong_count(&item), 1);
+}